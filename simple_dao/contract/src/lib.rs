@@ -2,8 +2,12 @@ use near_sdk::{
     AccountId, 
     Balance,
     env,
+    ext_contract,
     near_bindgen,
+    Gas,
     Promise,
+    PromiseOrValue,
+    PromiseResult,
     Duration
 };
 
@@ -14,6 +18,8 @@ use near_sdk::borsh::{
 };
 
 use near_sdk::collections::{
+    LookupMap,
+    TreeMap,
     UnorderedSet,
     UnorderedMap,
     Vector
@@ -25,18 +31,167 @@ use near_sdk::serde::{
 };
 
 use std::collections::HashMap;
+use std::ops::Bound;
 use near_sdk::json_types::{
+    Base58CryptoHash,
+    Base64VecU8,
+    U128,
+    U64,
     WrappedBalance,
-    WrappedDuration
+    WrappedDuration,
+    WrappedTimestamp
 };
 
-const CONSENSUS_PERCENTAGE: u128 = 50; 
+//Bumped whenever the layout of DAO changes, migrate converts from older versions
+const STATE_VERSION: u32 = 1;
+//Council weights are basis points and always add up to TOTAL_WEIGHT
+const TOTAL_WEIGHT: u128 = 10_000;
 
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+const GAS_FOR_CALLBACK: Gas = 10_000_000_000_000;
+
+//Rough size of a stored proposal, the bond has to at least pay for it
+const PROPOSAL_STORAGE_BYTES: u128 = 1_000;
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_NFT_TRANSFER: Gas = 20_000_000_000_000;
+const GAS_FOR_MIGRATE: Gas = 100_000_000_000_000;
+//Key near_bindgen keeps the contract root under
+const STATE_KEY: &[u8] = b"STATE";
+//Set once the votes of every proposal live under their own prefix
+const VOTES_MIGRATED_KEY: &[u8] = b"__votes_migrated";
+//Next legacy proposal to convert, present while migrate_legacy_proposals is not done
+const LEGACY_CURSOR_KEY: &[u8] = b"__legacy_cursor";
+//Number of legacy proposals, proposals added after migrate_from_legacy already use the new layout
+const LEGACY_END_KEY: &[u8] = b"__legacy_end";
+//The shared legacy votes map, kept until clear_legacy_votes has removed every entry
+const LEGACY_VOTES_KEY: &[u8] = b"__legacy_votes";
+
+//Proposals used to share the b"v" prefix for their votes,
+//the fixed width id keeps prefixes of different proposals from overlapping
+fn votes_prefix(id: u64) -> Vec<u8> {
+    let mut prefix = b"w".to_vec();
+    prefix.extend_from_slice(&id.to_le_bytes());
+    prefix
+}
+
+//Proposals that can still pass or be executed, a council member can not leave while their own are open
+fn is_open(status: &ProposalStatus) -> bool {
+    *status == ProposalStatus::Vote || *status == ProposalStatus::Queued
+}
+
+fn read_index(key: &[u8]) -> Option<u64> {
+    env::storage_read(key).map(|bytes| {
+        let mut index = [0u8; 8];
+        index.copy_from_slice(&bytes);
+        u64::from_le_bytes(index)
+    })
+}
+
+fn is_promise_success() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "Expected exactly one promise result"
+    );
+
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
+}
+
+#[ext_contract(ext_self)]
+pub trait DAOCallbacks {
+    fn on_executed(&mut self, id: u64);
+    fn on_council_removed(&mut self, id: u64, council: Council, delegations: Vec<(AccountId, Delegation)>);
+    fn on_bond_refunded(&mut self, id: u64, amount: U128);
+    fn on_ft_payout(&mut self, id: u64, token_id: AccountId, amount: U128);
+    fn on_nft_transferred(&mut self, id: u64, token: NftToken);
+    fn on_stake_refunded(&mut self, id: u64, account: AccountId, amount: U128);
+    fn on_withdrawn(&mut self, account: AccountId, unbonding: Unbonding);
+}
+
+#[ext_contract(ext_ft)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+#[ext_contract(ext_nft)]
+pub trait NonFungibleToken {
+    fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>);
+}
+
+//Collections nested in an index live under the index tag followed by the encoded key
+fn index_prefix<K: BorshSerialize>(
+    tag: &[u8],
+    key: &K
+    ) -> Vec<u8> {
+    let mut prefix = tag.to_vec();
+    prefix.extend(key.try_to_vec().unwrap());
+    prefix
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate="near_sdk::serde")]
 pub enum Vote {
     Yes, 
-    No
+    No,
+    Abstain
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub enum VoteBlocker {
+    Quorum,
+    Approval
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Default, Clone)]
+pub struct VoteTally {
+    yes: u128,
+    no: u128,
+    abstain: u128,
+    total: u128
+}
+
+impl VoteTally {
+    pub fn add(
+        &mut self,
+        vote: &Vote,
+        weight: u128
+        ) {
+        match vote {
+            Vote::Yes => self.yes += weight,
+            Vote::No => self.no += weight,
+            Vote::Abstain => self.abstain += weight
+        }
+    }
+
+    pub fn participation(&self) -> u128 {
+        self.yes + self.no + self.abstain
+    }
+
+    pub fn to_view(&self) -> VoteTallyView {
+        VoteTallyView {
+            yes: self.yes.into(),
+            no: self.no.into(),
+            abstain: self.abstain.into()
+        }
+    }
+
+    //Share of the electorate weight, in basis points
+    pub fn share_of(
+        &self,
+        weight: u128
+        ) -> u128 {
+        (weight * TOTAL_WEIGHT)
+            .checked_div(self.total)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate="near_sdk::serde")]
+pub struct VoteTallyView {
+    yes: U128,
+    no: U128,
+    abstain: U128
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -44,23 +199,312 @@ pub enum Vote {
 #[serde(crate="near_sdk::serde")]
 pub enum ProposalStatus {
     Vote, 
-    Success,
-    Fail
+    //Quorum was reached but the proposal was not approved, or it was overturned while queued
+    Fail,
+    Queued,
+    ExecutionFailed,
+    Cancelled,
+    //Voting period ended without reaching quorum
+    Expired,
+    Executed,
+    //Voting ended with the No share above the spam threshold, the bond is forfeited
+    Rejected
 }
 
 impl ProposalStatus {
+    //No further transitions are expected from a finalized proposal
     pub fn is_finalized(&self) -> bool {
-        self != &ProposalStatus::Vote
+        !matches!(
+            self,
+            ProposalStatus::Vote | ProposalStatus::Queued | ProposalStatus::ExecutionFailed
+        )
+    }
+
+    //Executed moves to ExecutionFailed when the execution promise fails, a failed execution
+    //can be retried, cancelled by a guardian or fail once it can no longer be executed
+    pub fn can_transition_to(
+        &self,
+        next: &ProposalStatus
+        ) -> bool {
+        matches!(
+            (self, next),
+            (ProposalStatus::Vote, ProposalStatus::Queued)
+                | (ProposalStatus::Vote, ProposalStatus::Fail)
+                | (ProposalStatus::Vote, ProposalStatus::Expired)
+                | (ProposalStatus::Vote, ProposalStatus::Cancelled)
+                | (ProposalStatus::Vote, ProposalStatus::Rejected)
+                | (ProposalStatus::Queued, ProposalStatus::Executed)
+                | (ProposalStatus::Queued, ProposalStatus::Fail)
+                | (ProposalStatus::Queued, ProposalStatus::Cancelled)
+                | (ProposalStatus::Executed, ProposalStatus::ExecutionFailed)
+                | (ProposalStatus::ExecutionFailed, ProposalStatus::Executed)
+                | (ProposalStatus::ExecutionFailed, ProposalStatus::Cancelled)
+                | (ProposalStatus::ExecutionFailed, ProposalStatus::Fail)
+        )
     }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+//Result of counting the votes, finalized turns it into the next ProposalStatus
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum VoteOutcome {
+    //Voting is still open and nothing is decided yet
+    Pending,
+    Passed,
+    Failed,
+    Expired,
+    Rejected
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
 #[serde(crate="near_sdk::serde")]
 #[serde(tag="type")]
 pub enum ProposalType {
+    //Filed by apply_for_council, amount is the stake escrowed with the application
     NewCouncil { amount: WrappedBalance },
     DeleteCouncil,
-    Payout { amount: WrappedBalance },
+    //Pays out NEAR, or tokens of token_id held by the DAO
+    Payout { amount: WrappedBalance, token_id: Option<AccountId> },
+    //The treasury only accepts tokens from allowed contracts, the proposal receiver is the contract
+    AllowToken,
+    DisallowToken,
+    //Fields left out keep their current value
+    ChangeConfig {
+        purpose: Option<String>,
+        bond: Option<WrappedBalance>,
+        vote_period: Option<WrappedDuration>,
+        grace_period: Option<WrappedDuration>
+    },
+    //Calls are batched against the proposal receiver
+    FunctionCall { actions: Vec<ActionCall> },
+    //Hash of a blob saved with store_blob
+    UpgradeSelf { hash: Base58CryptoHash },
+    TransferNft { nft_contract_id: AccountId, token_id: String },
+    //Role changes apply to the proposal receiver, only Member and Guardian are managed this way
+    AddToRole { role: Role },
+    RemoveFromRole { role: Role },
+    SetPermissions { kind: ProposalKind, permissions: RolePermissions },
+    //Replaces the whole council, see nominate, accept_nomination and vote_election
+    Election { seats: u64 },
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub struct NftToken {
+    nft_contract_id: AccountId,
+    token_id: String
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub struct ActionCall {
+    method_name: String,
+    args: Base64VecU8,
+    deposit: U128,
+    gas: U64
+}
+
+impl ProposalType {
+    pub fn kind(&self) -> ProposalKind {
+        match self {
+            ProposalType::NewCouncil { .. } => ProposalKind::NewCouncil,
+            ProposalType::DeleteCouncil => ProposalKind::DeleteCouncil,
+            ProposalType::Payout { .. } => ProposalKind::Payout,
+            ProposalType::AllowToken => ProposalKind::AllowToken,
+            ProposalType::DisallowToken => ProposalKind::DisallowToken,
+            ProposalType::ChangeConfig { .. } => ProposalKind::ChangeConfig,
+            ProposalType::FunctionCall { .. } => ProposalKind::FunctionCall,
+            ProposalType::UpgradeSelf { .. } => ProposalKind::UpgradeSelf,
+            ProposalType::TransferNft { .. } => ProposalKind::TransferNft,
+            ProposalType::AddToRole { .. } => ProposalKind::AddToRole,
+            ProposalType::RemoveFromRole { .. } => ProposalKind::RemoveFromRole,
+            ProposalType::SetPermissions { .. } => ProposalKind::SetPermissions,
+            ProposalType::Election { .. } => ProposalKind::Election,
+        }
+    }
+
+    pub fn assert_valid(&self) {
+        match self {
+            ProposalType::AddToRole { role } | ProposalType::RemoveFromRole { role } => {
+                assert!(
+                    *role == Role::Member || *role == Role::Guardian,
+                    "Only Member and Guardian roles are managed by proposals"
+                );
+            }
+            ProposalType::SetPermissions { permissions, .. } => permissions.assert_valid(),
+            ProposalType::Election { seats } => assert!(*seats > 0, "Election needs at least one seat"),
+            _ => {}
+        }
+
+        if let ProposalType::FunctionCall { actions } = self {
+            assert!(!actions.is_empty(), "FunctionCall must have at least one action");
+            for action in actions.iter() {
+                assert!(!action.method_name.is_empty(), "Method name can not be empty");
+                assert!(action.gas.0 > 0, "Each action needs some gas");
+            }
+        }
+
+        if let ProposalType::ChangeConfig { purpose, bond, vote_period, grace_period } = self {
+            assert!(
+                purpose.is_some() || bond.is_some() || vote_period.is_some() || grace_period.is_some(),
+                "ChangeConfig must update at least one field"
+            );
+            if let Some(bond) = bond {
+                assert!(
+                    bond.0 >= env::storage_byte_cost() * PROPOSAL_STORAGE_BYTES,
+                    "Bond must cover the storage of a proposal"
+                );
+            }
+            if let Some(vote_period) = vote_period {
+                assert!(vote_period.0 > 0, "Vote period must be positive");
+            }
+            if let Some(grace_period) = grace_period {
+                assert!(grace_period.0 > 0, "Grace period must be positive");
+            }
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[serde(crate="near_sdk::serde")]
+pub enum ProposalKind {
+    NewCouncil,
+    DeleteCouncil,
+    Payout,
+    AllowToken,
+    DisallowToken,
+    ChangeConfig,
+    FunctionCall,
+    UpgradeSelf,
+    TransferNft,
+    AddToRole,
+    RemoveFromRole,
+    SetPermissions,
+    Election,
+}
+
+impl ProposalKind {
+    pub fn all() -> Vec<ProposalKind> {
+        vec![
+            ProposalKind::NewCouncil,
+            ProposalKind::DeleteCouncil,
+            ProposalKind::Payout,
+            ProposalKind::AllowToken,
+            ProposalKind::DisallowToken,
+            ProposalKind::ChangeConfig,
+            ProposalKind::FunctionCall,
+            ProposalKind::UpgradeSelf,
+            ProposalKind::TransferNft,
+            ProposalKind::AddToRole,
+            ProposalKind::RemoveFromRole,
+            ProposalKind::SetPermissions,
+            ProposalKind::Election
+        ]
+    }
+}
+
+//Threshold and quorum are basis points of the electorate weight,
+//vote_period falls back to DAO::vote_period when not set
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub struct VotePolicy {
+    threshold: U128,
+    quorum: U128,
+    vote_period: Option<WrappedDuration>
+}
+
+impl VotePolicy {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.threshold.0 > 0 && self.threshold.0 <= TOTAL_WEIGHT,
+            "Threshold must be between 1 and 10000 basis points"
+        );
+        assert!(
+            self.quorum.0 <= TOTAL_WEIGHT,
+            "Quorum can not exceed 10000 basis points"
+        );
+        assert!(
+            self.vote_period.iter().all(|period| period.0 > 0),
+            "Vote period must be positive"
+        );
+    }
+}
+
+//Council, Guardian and Member are sets of accounts, Public is everyone
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[serde(crate="near_sdk::serde")]
+pub enum Role {
+    Council,
+    Member,
+    Guardian,
+    Public
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Action {
+    Create,
+    Vote,
+    Execute
+}
+
+//Roles allowed to create, vote on and execute proposals of one kind
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub struct RolePermissions {
+    create: Vec<Role>,
+    vote: Vec<Role>,
+    execute: Vec<Role>
+}
+
+//Matches the behaviour before roles: anyone with the bond creates, council votes, anyone executes
+impl Default for RolePermissions {
+    fn default() -> Self {
+        Self {
+            create: vec![Role::Public],
+            vote: vec![Role::Council],
+            execute: vec![Role::Public]
+        }
+    }
+}
+
+impl RolePermissions {
+    pub fn roles_for(
+        &self,
+        action: Action
+        ) -> &Vec<Role> {
+        match action {
+            Action::Create => &self.create,
+            Action::Vote => &self.vote,
+            Action::Execute => &self.execute
+        }
+    }
+
+    pub fn assert_valid(&self) {
+        assert!(
+            !self.create.is_empty() && !self.vote.is_empty() && !self.execute.is_empty(),
+            "Every action needs at least one role"
+        );
+        //The electorate is snapshotted at creation, so voters have to be a known set of accounts
+        assert!(!self.vote.contains(&Role::Public), "Public can not vote");
+    }
+}
+
+//No kinds means the delegation covers every proposal
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub struct Delegation {
+    delegate: AccountId,
+    kinds: Option<Vec<ProposalKind>>
+}
+
+impl Delegation {
+    pub fn covers(
+        &self,
+        kind: &ProposalKind
+        ) -> bool {
+        self.kinds
+            .iter()
+            .all(|kinds| kinds.contains(kind))
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
@@ -68,7 +512,11 @@ pub enum ProposalType {
 pub struct Council {
     account: AccountId,
     weight: u128,
-    locked_tokens: Balance 
+    locked_tokens: Balance,
+    //Proposals finalized in a row without a vote from this member
+    missed_votes: u64,
+    //Suspended members keep their stake but have no weight until they reactivate
+    suspended: bool
 }
 
 #[near_bindgen]
@@ -79,41 +527,247 @@ pub struct Proposal {
     receiver: AccountId,
     description: String,
     kind: ProposalType,
+    bond: Balance,
     vote_period_end: Duration,
-    votes: UnorderedMap<Council, Vote>
+    grace_period: Duration,
+    grace_period_end: Duration,
+    created_at: Duration,
+    //Members who leave or get suspended later are left out at tally time, see DAO::departed
+    electorate: HashMap<AccountId, u128>,
+    //Delegations covering this proposal when it was created, delegator to delegate
+    delegations: HashMap<AccountId, AccountId>,
+    votes: UnorderedMap<AccountId, Vote>,
+    //Frozen once the proposal is finalized
+    final_tally: Option<VoteTally>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate="near_sdk::serde")]
+pub struct ProposalView {
+    id: u64,
+    proposer: AccountId,
+    receiver: AccountId,
+    description: String,
+    kind: ProposalType,
+    status: ProposalStatus,
+    vote_period_end: WrappedTimestamp,
+    grace_period_end: WrappedTimestamp,
+    tally: VoteTallyView,
+    voter_count: u64
 }
 
 impl Proposal {
+    pub fn to_view(
+        &self,
+        id: u64,
+        dao: &DAO
+        ) -> ProposalView {
+        ProposalView {
+            id,
+            proposer: self.proposer.clone(),
+            receiver: self.receiver.clone(),
+            description: self.description.clone(),
+            kind: self.kind.clone(),
+            status: self.status.clone(),
+            vote_period_end: self.vote_period_end.into(),
+            grace_period_end: self.grace_period_end.into(),
+            tally: self.tally(dao).to_view(),
+            voter_count: self.votes.len()
+        }
+    }
+
     pub fn get_amount(&self) -> Option<Balance> {
         match self.kind {
-            ProposalType::Payout {amount} => Some(amount.0),
+            ProposalType::Payout {amount, ..} => Some(amount.0),
             _ => None,
         }
     }
 
-    pub fn vote_status(&self) -> ProposalStatus {
-        let zero: u128 = 0;
+    //Electorate without the members who departed after the proposal was created
+    pub fn active_electorate(
+        &self,
+        dao: &DAO
+        ) -> HashMap<AccountId, u128> {
+        self.electorate
+            .iter()
+            .filter(|(account, _)| !self.has_departed(dao, account))
+            .map(|(account, weight)| (account.clone(), *weight))
+            .collect()
+    }
+
+    pub fn has_departed(
+        &self,
+        dao: &DAO,
+        account: &AccountId
+        ) -> bool {
+        matches!(dao.departed.get(account), Some(departed_at) if departed_at >= self.created_at)
+    }
+
+    pub fn tally(
+        &self,
+        dao: &DAO
+        ) -> VoteTally {
+        if let Some(tally) = &self.final_tally {
+            return tally.clone();
+        }
 
-        let percent: u128 = self.votes
+        let electorate = self.active_electorate(dao);
+        let mut tally = VoteTally {
+            total: electorate.values().sum(),
+            ..VoteTally::default()
+        };
+        let direct_votes: HashMap<AccountId, Vote> = self.votes
             .iter()
-            .map(|(k, v)| {
-                match v {
-                    Vote::Yes => k.weight,
-                    Vote::No => zero
-                }
-            })
-        .sum();
+            .collect();
+
+        //Delegated weight follows the delegate unless the delegator voted directly
+        for (account, weight) in electorate.iter() {
+            let cast_vote = match direct_votes.get(account) {
+                Some(v) => Some(v),
+                None => self.delegations
+                    .get(account)
+                    .filter(|delegate| electorate.contains_key(*delegate))
+                    .and_then(|delegate| direct_votes.get(delegate))
+            };
+
+            if let Some(v) = cast_vote {
+                tally.add(v, *weight);
+            }
+        }
+
+        tally
+    }
+
+    //Quorum counts every participating vote, abstentions included,
+    //approval only looks at Yes against No
+    pub fn vote_blocker(
+        &self,
+        dao: &DAO
+        ) -> Option<VoteBlocker> {
+        let policy = dao.policy_for(&self.kind);
+        let tally = self.tally(dao);
+
+        if tally.share_of(tally.participation()) < policy.quorum.0 {
+            Some(VoteBlocker::Quorum)
+        } else if tally.share_of(tally.yes) < policy.threshold.0 || tally.yes <= tally.no {
+            Some(VoteBlocker::Approval)
+        } else {
+            None
+        }
+    }
+
+    pub fn vote_status(
+        &self,
+        dao: &DAO
+        ) -> VoteOutcome {
+        let blocker = self.vote_blocker(dao);
+        let tally = self.tally(dao);
 
-        if percent >= CONSENSUS_PERCENTAGE {
-            ProposalStatus::Success
-        } else if percent < CONSENSUS_PERCENTAGE && env::block_timestamp() > self.vote_period_end {
-            ProposalStatus::Fail
+        if blocker.is_none() {
+            VoteOutcome::Passed
+        } else if env::block_timestamp() <= self.vote_period_end {
+            VoteOutcome::Pending
+        } else if tally.share_of(tally.no) > dao.spam_threshold {
+            VoteOutcome::Rejected
+        } else if blocker == Some(VoteBlocker::Quorum) {
+            VoteOutcome::Expired
         } else {
-            ProposalStatus::Vote
+            VoteOutcome::Failed
         } 
     }
 }
 
+//A nominee takes part once they accept the nomination, council members can accept without new stake
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Candidate {
+    accepted: bool,
+    stake: Balance
+}
+
+//Ballots name up to `seats` accepted candidates, each gets the full weight of the voter
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Election {
+    seats: u64,
+    candidates: HashMap<AccountId, Candidate>,
+    ballots: HashMap<AccountId, Vec<AccountId>>
+}
+
+impl Election {
+    //Weight of the ballots cast for every candidate
+    pub fn results(
+        &self,
+        electorate: &HashMap<AccountId, u128>
+        ) -> HashMap<AccountId, u128> {
+        let mut results: HashMap<AccountId, u128> = self.candidates
+            .keys()
+            .map(|account| (account.clone(), 0))
+            .collect();
+        for (voter, choices) in self.ballots.iter() {
+            let weight = electorate.get(voter).copied().unwrap_or(0);
+            for choice in choices.iter() {
+                *results.entry(choice.clone()).or_insert(0) += weight;
+            }
+        }
+        results
+    }
+
+    //Accepted candidates with the most weight, ties go to the lower account id
+    pub fn winners(
+        &self,
+        electorate: &HashMap<AccountId, u128>
+        ) -> Vec<AccountId> {
+        let results = self.results(electorate);
+        let mut ranked: Vec<(AccountId, u128)> = results
+            .into_iter()
+            .filter(|(account, _)| self.candidates[account].accepted)
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.into_iter()
+            .take(self.seats as usize)
+            .map(|(account, _)| account)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate="near_sdk::serde")]
+pub struct CandidateView {
+    account: AccountId,
+    accepted: bool,
+    stake: WrappedBalance,
+    votes: U128
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate="near_sdk::serde")]
+pub struct ElectionView {
+    seats: u64,
+    candidates: Vec<CandidateView>,
+    voter_count: u64
+}
+
+//Stake of a member who left the council, released once the unbonding period is over
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate="near_sdk::serde")]
+pub struct Unbonding {
+    amount: WrappedBalance,
+    release_at: WrappedTimestamp
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate="near_sdk::serde")]
+pub struct ConfigView {
+    purpose: String,
+    bond: WrappedBalance,
+    vote_period: WrappedDuration,
+    grace_period: WrappedDuration,
+    unbonding_period: WrappedDuration,
+    min_stake: WrappedBalance,
+    council_term: WrappedDuration,
+    term_end: WrappedTimestamp,
+    max_missed_votes: u64
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate="near_sdk::serde")]
 pub struct ProposalInput {
@@ -125,12 +779,95 @@ pub struct ProposalInput {
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct DAO {
+    version: u32,
+    purpose: String,
+    bond: Balance,
+    vote_period: Duration,
+    grace_period: Duration,
+    unbonding_period: Duration,
+    min_stake: Balance,
+    council_term: Duration,
+    term_end: Duration,
+    max_missed_votes: u64,
+    cancel_penalty: u128,
+    spam_threshold: u128,
+    policy: UnorderedMap<ProposalKind, VotePolicy>,
+    council: UnorderedMap<AccountId, Council>,
+    delegations: UnorderedMap<AccountId, Delegation>,
+    guardians: UnorderedSet<AccountId>,
+    proposals: Vector<Proposal>,
+    //Ordered by id so pages stay stable while proposals change status
+    proposals_by_status: LookupMap<ProposalStatus, TreeMap<u64, ()>>,
+    proposals_by_proposer: LookupMap<AccountId, Vector<u64>>,
+    proposals_by_receiver: LookupMap<AccountId, Vector<u64>>,
+    proposals_by_kind: LookupMap<ProposalKind, Vector<u64>>,
+    blobs: LookupMap<Base58CryptoHash, Vec<u8>>,
+    ft_balances: UnorderedMap<AccountId, Balance>,
+    token_contracts: UnorderedSet<AccountId>,
+    nfts: UnorderedSet<NftToken>,
+    applications: LookupMap<AccountId, u64>,
+    unbonding: LookupMap<AccountId, Unbonding>,
+    members: UnorderedSet<AccountId>,
+    permissions: UnorderedMap<ProposalKind, RolePermissions>,
+    elections: LookupMap<u64, Election>,
+    //Last time a member left the council or was suspended
+    departed: LookupMap<AccountId, Duration>,
+    //Proposals in Vote or Queued by proposer, and DeleteCouncil proposals in Vote or Queued by receiver
+    open_proposals: LookupMap<AccountId, u64>,
+    open_removals: LookupMap<AccountId, u64>,
+    //NEAR held for others: bonds, application and candidate stakes, council stakes and unbonding stakes.
+    //Only the balance above it belongs to the treasury
+    locked_funds: Balance
+}
+
+//Layout of the contract before per-proposal votes, only read by the legacy migration
+#[derive(BorshSerialize, BorshDeserialize)]
+struct LegacyCouncil {
+    account: AccountId,
+    weight: u128,
+    locked_tokens: Balance
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+enum LegacyVote {
+    Yes,
+    No
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+enum LegacyStatus {
+    Vote,
+    Success,
+    Fail
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+enum LegacyProposalType {
+    NewCouncil { amount: WrappedBalance },
+    DeleteCouncil,
+    Payout { amount: WrappedBalance }
+}
+
+//Every legacy proposal points at the same votes map under b"v"
+#[derive(BorshSerialize, BorshDeserialize)]
+struct LegacyProposal {
+    status: LegacyStatus,
+    proposer: AccountId,
+    receiver: AccountId,
+    description: String,
+    kind: LegacyProposalType,
+    vote_period_end: Duration,
+    votes: UnorderedMap<LegacyCouncil, LegacyVote>
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct LegacyDAO {
     purpose: String,
     bond: Balance,
     vote_period: Duration,
     grace_period: Duration,
-    council: UnorderedSet<Council>,
-    proposals: Vector<Proposal>
+    council: UnorderedSet<LegacyCouncil>,
+    proposals: Vector<LegacyProposal>
 }
 
 impl Default for DAO {
@@ -143,31 +880,53 @@ impl Default for DAO {
 impl DAO {
     #[init] 
     #[payable]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         _purpose: String,
         _bond: WrappedBalance,
         _vote_period: WrappedDuration,
-        _grace_period: WrappedDuration
+        _grace_period: WrappedDuration,
+        _unbonding_period: WrappedDuration,
+        _min_stake: WrappedBalance,
+        _council_term: WrappedDuration,
+        _max_missed_votes: u64,
+        _cancel_penalty: U128,
+        _spam_threshold: U128,
+        _policy: HashMap<ProposalKind, VotePolicy>,
+        _guardians: Vec<AccountId>
         ) -> Self {
         assert!(!env::state_exists(), "DAO contract is already initialized");
+        let mut dao = Self::with_config(
+            _purpose,
+            _bond.into(),
+            _vote_period.into(),
+            _grace_period.into(),
+            _unbonding_period,
+            _min_stake,
+            _council_term,
+            _max_missed_votes,
+            _cancel_penalty,
+            _spam_threshold,
+            _policy,
+            _guardians
+        );
 
-        let mut dao = Self {
-            purpose: _purpose,
-            bond: _bond.into(),
-            vote_period: _vote_period.into(),
-            grace_period: _grace_period.into(),
-            council: UnorderedSet::new(b"c".to_vec()),
-            proposals: Vector::new(b"p".to_vec()),
-        };
+        assert!(env::attached_deposit() >= dao.min_stake, "Owner stake is below the minimum stake");
 
         let zero: u128 = 0;
         let owner = Council {
             account: env::predecessor_account_id(),
             weight: zero,
-            locked_tokens: env::attached_deposit()
+            locked_tokens: env::attached_deposit(),
+            missed_votes: 0,
+            suspended: false
         };
 
-        dao.council.insert(&owner);
+        dao.council.insert(&owner.account, &owner);
+        dao.locked_funds += owner.locked_tokens;
+        dao.recompute_weights();
+
+        env::storage_write(VOTES_MIGRATED_KEY, &[1]);
         dao
     }
 
@@ -177,35 +936,429 @@ impl DAO {
         _proposal: ProposalInput
         ) -> u64 {
         assert!(env::attached_deposit() >= self.bond, "Not enough deposit");
+        assert!(
+            _proposal.kind.kind() != ProposalKind::NewCouncil,
+            "Candidates apply themselves with apply_for_council"
+        );
+        self.assert_permitted(_proposal.kind.kind(), Action::Create, &env::predecessor_account_id());
 
-        let p = Proposal {
-            status: ProposalStatus::Vote,
-            proposer: env::predecessor_account_id(),
-            receiver: _proposal.target,
-            description: _proposal.description,
-            kind: _proposal.kind,
-            vote_period_end: env::block_timestamp() + self.vote_period,
-            votes: UnorderedMap::new(b"v".to_vec())
-        };
-
-        self.proposals.push(&p);
-        self.proposals.len() - 1
+        self.create_proposal(env::predecessor_account_id(), _proposal)
     }
 
-    pub fn get_proposals_by_status(
-        &self,
-        _status: ProposalStatus
-        ) -> HashMap<u64, Proposal> {
-        let ids: Vec<u64> = (0..self.proposals.len())
-            .filter(|index| self.proposals.get(index.clone()).unwrap().status == _status) 
-            .collect();
-
-        (0..ids.len())
-            .map(|index| {
-                let id: u64 = ids[index as usize];
-                (id, self.proposals.get(id).unwrap())
-            })
-        .collect()
+    //The attached deposit above the bond is the stake, it stays in escrow until the vote ends
+    #[payable]
+    pub fn apply_for_council(
+        &mut self,
+        description: String
+        ) -> u64 {
+        let applicant = env::predecessor_account_id();
+        assert!(!self.is_council(&applicant), "Already a council member");
+        self.assert_permitted(ProposalKind::NewCouncil, Action::Create, &applicant);
+        assert!(
+            self.applications.get(&applicant).is_none(),
+            "There is already a pending application"
+        );
+        assert!(
+            env::attached_deposit() > self.bond,
+            "Attach the bond plus the stake to apply"
+        );
+
+        let stake = env::attached_deposit() - self.bond;
+        assert!(stake >= self.min_stake, "Stake is below the minimum stake");
+        let id = self.create_proposal(
+            applicant.clone(),
+            ProposalInput {
+                target: applicant.clone(),
+                description,
+                kind: ProposalType::NewCouncil { amount: stake.into() }
+            }
+        );
+        self.applications.insert(&applicant, &id);
+        self.locked_funds += stake;
+
+        env::log(format!("{} applied for council with a stake of {}", applicant, stake).as_bytes());
+        id
+    }
+
+    pub fn get_application(
+        &self,
+        account: AccountId
+        ) -> Option<u64> {
+        self.applications.get(&account)
+    }
+
+    //Leaves the council without a DeleteCouncil vote. The member's weight no longer counts on
+    //pending proposals and the stake can be withdrawn after the unbonding period
+    pub fn leave_council(&mut self) {
+        let account = env::predecessor_account_id();
+        let council = self.council.get(&account).expect("Only council members can leave the council");
+        assert!(self.council.len() > 1, "The last council member can not leave");
+
+        assert!(
+            self.open_proposals.get(&account).unwrap_or(0) == 0,
+            "Cancel or finalize your pending proposals before leaving"
+        );
+        //Leaving must not dodge a pending removal, the stake is returned by DeleteCouncil then
+        assert!(
+            self.open_removals.get(&account).unwrap_or(0) == 0,
+            "There is a pending DeleteCouncil proposal against this member"
+        );
+
+        self.council.remove(&account);
+        self.remove_delegations(&account);
+        self.recompute_weights();
+        self.departed.insert(&account, &env::block_timestamp());
+
+        let unbonding = self.start_unbonding(
+            &account,
+            council.locked_tokens,
+            env::block_timestamp() + self.unbonding_period
+        );
+
+        env::log(format!(
+            "{} left the council, {} unbonding until {}",
+            account, council.locked_tokens, unbonding.release_at.0
+        ).as_bytes());
+    }
+
+    #[payable]
+    pub fn increase_stake(&mut self) {
+        let account = env::predecessor_account_id();
+        let mut council = self.council.get(&account).expect("Only council members can stake");
+        assert!(env::attached_deposit() > 0, "Attach the amount to stake");
+
+        council.locked_tokens += env::attached_deposit();
+        self.locked_funds += env::attached_deposit();
+        self.council.insert(&account, &council);
+        self.recompute_weights();
+
+        env::log(format!("{} increased stake to {}", account, council.locked_tokens).as_bytes());
+    }
+
+    //Part of the stake goes through the unbonding period before it can be withdrawn
+    pub fn request_unstake(
+        &mut self,
+        amount: U128
+        ) {
+        let account = env::predecessor_account_id();
+        let mut council = self.council.get(&account).expect("Only council members can unstake");
+        assert!(amount.0 > 0, "Amount to unstake must be positive");
+        assert!(
+            council.locked_tokens >= amount.0 && council.locked_tokens - amount.0 >= self.min_stake,
+            "Remaining stake would fall below the minimum stake"
+        );
+
+        council.locked_tokens -= amount.0;
+        self.council.insert(&account, &council);
+        self.recompute_weights();
+
+        let unbonding = self.start_unbonding(
+            &account,
+            amount.0,
+            env::block_timestamp() + self.unbonding_period
+        );
+
+        env::log(format!(
+            "{} unstaking {}, {} unbonding until {}",
+            account, amount.0, unbonding.amount.0, unbonding.release_at.0
+        ).as_bytes());
+    }
+
+    pub fn withdraw(&mut self) {
+        let account = env::predecessor_account_id();
+        let unbonding = self.unbonding.get(&account).expect("Nothing to withdraw");
+        assert!(
+            env::block_timestamp() >= unbonding.release_at.0,
+            "Unbonding period has not ended yet"
+        );
+
+        self.unbonding.remove(&account);
+        self.locked_funds -= unbonding.amount.0;
+
+        Promise::new(account.clone())
+            .transfer(unbonding.amount.0)
+            .then(ext_self::on_withdrawn(
+                account,
+                unbonding,
+                &env::current_account_id(),
+                0,
+                GAS_FOR_CALLBACK
+            ));
+    }
+
+    #[private]
+    pub fn on_withdrawn(
+        &mut self,
+        account: AccountId,
+        unbonding: Unbonding
+        ) {
+        if is_promise_success() {
+            env::log(format!("{} withdrew {}", account, unbonding.amount.0).as_bytes());
+        } else {
+            self.locked_funds += unbonding.amount.0;
+            //Stake unbonded in the meantime keeps its own release date
+            let restored = match self.unbonding.get(&account) {
+                Some(pending) => Unbonding {
+                    amount: (pending.amount.0 + unbonding.amount.0).into(),
+                    release_at: pending.release_at
+                },
+                None => unbonding
+            };
+            self.unbonding.insert(&account, &restored);
+
+            env::log(format!("Withdrawal of {} failed, it can be retried with withdraw", account).as_bytes());
+        }
+    }
+
+    pub fn get_unbonding(
+        &self,
+        account: AccountId
+        ) -> Option<Unbonding> {
+        self.unbonding.get(&account)
+    }
+
+    pub fn get_proposal(
+        &self,
+        id: u64
+        ) -> ProposalView {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+        proposal.to_view(id, self)
+    }
+
+    pub fn get_proposals(
+        &self,
+        from_index: u64,
+        limit: u64
+        ) -> Vec<ProposalView> {
+        (from_index..std::cmp::min(from_index.saturating_add(limit), self.proposals.len()))
+            .map(|id| self.proposals.get(id).unwrap().to_view(id, self))
+            .collect()
+    }
+
+    //Pages by proposal id, the next page starts after the last id returned
+    pub fn get_proposals_by_status(
+        &self,
+        _status: ProposalStatus,
+        from_id: u64,
+        limit: u64
+        ) -> Vec<ProposalView> {
+        match self.proposals_by_status.get(&_status) {
+            Some(ids) => ids
+                .range((Bound::Included(from_id), Bound::Unbounded))
+                .take(limit as usize)
+                .map(|(id, _)| self.proposals.get(id).unwrap().to_view(id, self))
+                .collect(),
+            None => vec![]
+        }
+    }
+
+    pub fn get_proposals_by_proposer(
+        &self,
+        proposer: AccountId,
+        from_index: u64,
+        limit: u64
+        ) -> Vec<ProposalView> {
+        match self.proposals_by_proposer.get(&proposer) {
+            Some(ids) => self.proposal_views(&ids, from_index, limit),
+            None => vec![]
+        }
+    }
+
+    pub fn get_proposals_by_receiver(
+        &self,
+        receiver: AccountId,
+        from_index: u64,
+        limit: u64
+        ) -> Vec<ProposalView> {
+        match self.proposals_by_receiver.get(&receiver) {
+            Some(ids) => self.proposal_views(&ids, from_index, limit),
+            None => vec![]
+        }
+    }
+
+    pub fn get_proposals_by_kind(
+        &self,
+        kind: ProposalKind,
+        from_index: u64,
+        limit: u64
+        ) -> Vec<ProposalView> {
+        match self.proposals_by_kind.get(&kind) {
+            Some(ids) => self.proposal_views(&ids, from_index, limit),
+            None => vec![]
+        }
+    }
+
+    pub fn get_policy(&self) -> HashMap<ProposalKind, VotePolicy> {
+        self.policy
+            .iter()
+            .collect()
+    }
+
+    pub fn get_vote_blocker(
+        &self,
+        id: u64
+        ) -> Option<VoteBlocker> {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+        proposal.vote_blocker(self)
+    }
+
+    pub fn get_electorate(
+        &self,
+        id: u64
+        ) -> HashMap<AccountId, U128> {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+        proposal.active_electorate(self)
+            .into_iter()
+            .map(|(account, weight)| (account, weight.into()))
+            .collect()
+    }
+
+    pub fn get_guardians(&self) -> Vec<AccountId> {
+        self.guardians.to_vec()
+    }
+
+    pub fn get_members(&self) -> Vec<AccountId> {
+        self.members.to_vec()
+    }
+
+    pub fn get_roles(
+        &self,
+        account: AccountId
+        ) -> Vec<Role> {
+        vec![Role::Council, Role::Member, Role::Guardian, Role::Public]
+            .into_iter()
+            .filter(|role| self.has_role(&account, *role))
+            .collect()
+    }
+
+    pub fn get_permissions(&self) -> HashMap<ProposalKind, RolePermissions> {
+        self.permissions.iter().collect()
+    }
+
+    pub fn get_delegation(
+        &self,
+        account: AccountId
+        ) -> Option<Delegation> {
+        self.delegations.get(&account)
+    }
+
+    pub fn get_ft_balance(
+        &self,
+        token_id: AccountId
+        ) -> U128 {
+        self.ft_balances.get(&token_id).unwrap_or(0).into()
+    }
+
+    pub fn get_ft_balances(&self) -> HashMap<AccountId, U128> {
+        self.ft_balances
+            .iter()
+            .map(|(token_id, balance)| (token_id, balance.into()))
+            .collect()
+    }
+
+    pub fn get_token_contracts(&self) -> Vec<AccountId> {
+        self.token_contracts.to_vec()
+    }
+
+    //NEP-141 receiver, the calling token contract is credited to the treasury.
+    //Contracts that are not allowed get the whole amount back, so no storage is spent on them
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String
+        ) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        if !self.token_contracts.contains(&token_id) {
+            env::log(format!("Refused {} of {}, the token is not allowed", amount.0, token_id).as_bytes());
+            return PromiseOrValue::Value(amount);
+        }
+
+        let balance = self.ft_balances.get(&token_id).unwrap_or(0);
+        self.ft_balances.insert(&token_id, &(balance + amount.0));
+
+        env::log(format!("Received {} of {} from {}: {}", amount.0, token_id, sender_id, msg).as_bytes());
+        PromiseOrValue::Value(U128(0))
+    }
+
+    pub fn get_nfts(
+        &self,
+        from_index: u64,
+        limit: u64
+        ) -> Vec<NftToken> {
+        let tokens = self.nfts.as_vector();
+        (from_index..std::cmp::min(from_index.saturating_add(limit), tokens.len()))
+            .map(|index| tokens.get(index).unwrap())
+            .collect()
+    }
+
+    //NEP-171 receiver, the DAO keeps every token sent by an allowed contract and returns the rest
+    pub fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: String,
+        msg: String
+        ) -> PromiseOrValue<bool> {
+        let token = NftToken {
+            nft_contract_id: env::predecessor_account_id(),
+            token_id
+        };
+        if !self.token_contracts.contains(&token.nft_contract_id) {
+            env::log(format!(
+                "Refused token {} of {}, the contract is not allowed",
+                token.token_id, token.nft_contract_id
+            ).as_bytes());
+            return PromiseOrValue::Value(true);
+        }
+        self.nfts.insert(&token);
+
+        env::log(format!(
+            "Received token {} of {} from {} on behalf of {}: {}",
+            token.token_id, token.nft_contract_id, sender_id, previous_owner_id, msg
+        ).as_bytes());
+        PromiseOrValue::Value(false)
+    }
+
+    pub fn delegate(
+        &mut self,
+        delegate: AccountId,
+        kinds: Option<Vec<ProposalKind>>
+        ) {
+        let delegator = env::predecessor_account_id();
+        assert!(
+            self.is_council(&delegator),
+            "Only council can delegate"
+        );
+        assert!(
+            self.is_council(&delegate),
+            "Can only delegate to a council member"
+        );
+        assert!(
+            delegator != delegate,
+            "Can not delegate to yourself"
+        );
+        assert!(
+            kinds.iter().all(|kinds| !kinds.is_empty()),
+            "Delegation must cover at least one proposal kind"
+        );
+
+        let delegation = Delegation {
+            delegate: delegate.clone(),
+            kinds
+        };
+        self.delegations.insert(&delegator, &delegation);
+
+        env::log(format!("{} delegated vote to {}", delegator, delegate).as_bytes());
+    }
+
+    pub fn revoke_delegation(&mut self) {
+        let delegator = env::predecessor_account_id();
+        let delegation = self.delegations
+            .remove(&delegator)
+            .expect("No delegation to revoke");
+
+        env::log(format!("{} revoked delegation to {}", delegator, delegation.delegate).as_bytes());
     }
 
     pub fn vote(
@@ -213,51 +1366,56 @@ impl DAO {
         id: u64, 
         vote: Vote
         ) {
-        let councils: Vec<Council> = self.council
-            .iter()
-            .filter(|item| item.account == env::predecessor_account_id())
-            .collect();
+        let voter = env::predecessor_account_id();
+        
+        let mut proposal = match self.proposal_for_voting(id) {
+            Some(proposal) => proposal,
+            None => return
+        };
+        assert!(
+            proposal.kind.kind() != ProposalKind::Election,
+            "Use vote_election to vote in an election"
+        );
+        self.assert_permitted(proposal.kind.kind(), Action::Vote, &voter);
 
         assert!(
-            councils.len() > 0,
-            "Only council can vote"
+            proposal.electorate.contains_key(&voter) && !proposal.has_departed(self, &voter),
+            "Not in the electorate of this proposal"
         );
-        
-        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
-        assert_eq!(
-            proposal.status,
-            ProposalStatus::Vote,
-            "Proposal already finalized"
+
+        let previous_vote = proposal.votes.insert(&voter, &vote);
+        assert!(
+            previous_vote.as_ref() != Some(&vote),
+            "Already voted"
         );
 
-        if proposal.vote_period_end < env::block_timestamp() {
-            env::log(b"Voting period expried, finalizing the proposal");
-            self.finalized(id);
-            return;
+        if previous_vote.is_some() {
+            env::log(format!("{} changed vote on proposal {} to {:?}", voter, id, vote).as_bytes());
+        } else {
+            env::log(format!("{} voted {:?} on proposal {}", voter, vote, id).as_bytes());
         }
 
-        let already_votes: Vec<Council> = proposal.votes
-            .keys()
-            .filter(|k| k.account == env::predecessor_account_id())
-            .collect();
+        self.update_vote_status(id, &mut proposal);
+    }
 
-        assert!(
-            already_votes.len() == 0,
-            "Already voted"
-        );
+    pub fn retract_vote(
+        &mut self,
+        id: u64
+        ) {
+        let mut proposal = match self.proposal_for_voting(id) {
+            Some(proposal) => proposal,
+            None => return
+        };
 
-        let council = councils.get(0).clone().unwrap();
-        
-        proposal.votes.insert(council, &vote);
-        let post_status = proposal.vote_status();
+        let voter = env::predecessor_account_id();
+        proposal.votes
+            .remove(&voter)
+            .expect("No vote to retract");
 
-        //Update status after voting
-        proposal.status = post_status.clone();
-        self.proposals.replace(id, &proposal);
+        env::log(format!("{} retracted vote on proposal {}", voter, id).as_bytes());
 
-        if post_status.is_finalized() {
-            self.finalized(id);
-        }
+        //Removing a No vote can be enough for the proposal to pass
+        self.update_vote_status(id, &mut proposal);
     }
 
     pub fn finalized(
@@ -266,75 +1424,3067 @@ impl DAO {
         ) {
         let mut proposal = self.proposals.get(id).expect("No proposal with such id");
 
-        assert!(
-            !proposal.status.is_finalized(),
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::Vote,
             "Proposal already finalized"
         );
 
-        proposal.status = proposal.vote_status();
-        match proposal.status {
-            ProposalStatus::Success => {
-                env::log(b"Vote succeded");
+        let outcome = match proposal.kind {
+            ProposalType::Election { .. } => self.election_status(id, &proposal),
+            _ => proposal.vote_status(self)
+        };
+        if outcome != VoteOutcome::Pending {
+            self.record_participation(id, &proposal);
+        }
 
-                let target = proposal.receiver.clone();
-                //Send bond back to proposer
-                Promise::new(proposal.proposer.clone()).transfer(self.bond);
-
-                match proposal.kind {
-                    ProposalType::NewCouncil { amount } => {
-                        let council = Council {
-                            account: target,
-                            weight: 10, //HARD CODE
-                            locked_tokens: amount.0 
-                        };
-
-                        self.council.insert(&council);
-                        self.recompute_percentage();
-                    }
+        match outcome {
+            VoteOutcome::Passed => {
+                env::log(b"Vote succeded, proposal queued for execution");
 
-                    ProposalType::DeleteCouncil => {
-                        let councils: Vec<Council> = self.council
-                            .iter()
-                            .filter(|item| item.account == target)
-                            .collect();
+                proposal.grace_period_end = env::block_timestamp() + proposal.grace_period;
+                self.set_status(id, &mut proposal, ProposalStatus::Queued);
+                self.proposals.replace(id, &proposal);
 
-                        let council = councils.get(0).clone().unwrap();
-                        let locked_tokens = council.locked_tokens;
+                //Send bond back to proposer
+                self.send_bond_back(id, &mut proposal);
+            }
 
-                        Promise::new(council.account.clone()).transfer(locked_tokens);
-                        
-                        self.council.remove(&council);
-                        self.recompute_percentage();
-                    } 
+            VoteOutcome::Failed => {
+                env::log(b"Proposal vote failed: not enough approval");
 
-                    ProposalType::Payout { amount } => {
-                        Promise::new(target).transfer(amount.0);
-                    }
-                }
+                self.set_status(id, &mut proposal, ProposalStatus::Fail);
+                self.proposals.replace(id, &proposal);
+
+                //Send bond back to proposer
+                self.send_bond_back(id, &mut proposal);
+                self.send_stake_back(id, &proposal);
             }
 
-            ProposalStatus::Fail => {
-                env::log(b"Proposal vote failed");
+            VoteOutcome::Expired => {
+                env::log(b"Proposal expired: quorum not reached");
+
+                self.set_status(id, &mut proposal, ProposalStatus::Expired);
+                self.proposals.replace(id, &proposal);
+
                 //Send bond back to proposer
-                Promise::new(proposal.proposer.clone()).transfer(self.bond); 
+                self.send_bond_back(id, &mut proposal);
+                self.send_stake_back(id, &proposal);
             }
 
-            ProposalStatus::Vote => {
+            VoteOutcome::Rejected => {
+                env::log(format!("Proposal {} rejected as spam, bond of {} forfeited", id, proposal.bond).as_bytes());
+
+                //Bond stays with the DAO
+                self.locked_funds -= proposal.bond;
+                proposal.bond = 0;
+                self.set_status(id, &mut proposal, ProposalStatus::Rejected);
+                self.proposals.replace(id, &proposal);
+                self.send_stake_back(id, &proposal);
+            }
+
+            VoteOutcome::Pending => {
                 env::panic(b"Voting period has not expired and no majority vote yet");
             }
         }
     }
 
-    fn recompute_percentage(
-        &mut self
+    pub fn execute(
+        &mut self,
+        id: u64
         ) {
-        let sum: Balance = self.council
-            .iter()
-            .map(|item| item.locked_tokens)
-            .sum();
+        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
+
+        //A failed execution can be retried
+        assert!(
+            proposal.status == ProposalStatus::Queued || proposal.status == ProposalStatus::ExecutionFailed,
+            "Proposal is not queued for execution"
+        );
+        assert!(
+            env::block_timestamp() >= proposal.grace_period_end,
+            "Grace period has not ended yet"
+        );
+        self.assert_permitted(proposal.kind.kind(), Action::Execute, &env::predecessor_account_id());
 
-        for mut item in self.council.iter() {
-            item.weight = item.locked_tokens / sum;
+        //Members who left or were suspended since the vote no longer count towards the majority
+        if proposal.status == ProposalStatus::Queued
+            && proposal.kind.kind() != ProposalKind::Election
+            && proposal.vote_blocker(self).is_some() {
+            self.set_status(id, &mut proposal, ProposalStatus::Fail);
+            self.proposals.replace(id, &proposal);
+            self.send_stake_back(id, &proposal);
+
+            env::log(format!("Queued proposal {} lost its majority and failed", id).as_bytes());
+            return;
+        }
+
+        //Another proposal may have already done what this one was about, then it can never run
+        if let Some(reason) = self.execution_blocker(&proposal) {
+            self.set_status(id, &mut proposal, ProposalStatus::Fail);
+            self.proposals.replace(id, &proposal);
+            self.send_stake_back(id, &proposal);
+
+            env::log(format!("Proposal {} can no longer be executed: {}", id, reason).as_bytes());
+            return;
         }
+
+        self.set_status(id, &mut proposal, ProposalStatus::Executed);
+        self.proposals.replace(id, &proposal);
+
+        env::log(format!("Executing proposal {}", id).as_bytes());
+
+        let target = proposal.receiver.clone();
+        match proposal.kind {
+            ProposalType::NewCouncil { amount } => {
+                //The escrowed stake becomes the locked tokens of the new member,
+                //an applicant elected in the meantime adds it to the stake they already have.
+                //Legacy applications escrowed nothing, their stake comes out of the treasury as it used to
+                if self.applications.get(&target) == Some(id) {
+                    self.applications.remove(&target);
+                } else {
+                    self.assert_free_balance(amount.0);
+                    self.locked_funds += amount.0;
+                }
+
+                let zero: u128 = 0;
+                let council = match self.council.get(&target) {
+                    Some(council) => Council {
+                        locked_tokens: council.locked_tokens + amount.0,
+                        ..council
+                    },
+                    None => Council {
+                        account: target,
+                        weight: zero,
+                        locked_tokens: amount.0,
+                        missed_votes: 0,
+                        suspended: false
+                    }
+                };
+
+                self.council.insert(&council.account, &council);
+                self.recompute_weights();
+            }
+
+            ProposalType::DeleteCouncil => {
+                let council = self.council
+                    .remove(&target)
+                    .expect("Receiver is not a council member");
+                let delegations = self.remove_delegations(&council.account);
+
+                self.recompute_weights();
+                self.locked_funds -= council.locked_tokens;
+
+                Promise::new(council.account.clone())
+                    .transfer(council.locked_tokens)
+                    .then(ext_self::on_council_removed(
+                        id,
+                        council,
+                        delegations,
+                        &env::current_account_id(),
+                        0,
+                        GAS_FOR_CALLBACK
+                    ));
+            } 
+
+            ProposalType::Payout { amount, token_id: Some(token_id) } => {
+                let balance = self.ft_balances.get(&token_id).unwrap_or(0);
+                assert!(balance >= amount.0, "Not enough tokens in the treasury");
+                self.ft_balances.insert(&token_id, &(balance - amount.0));
+
+                ext_ft::ft_transfer(
+                    target,
+                    amount,
+                    Some(format!("Payout of proposal {}", id)),
+                    &token_id,
+                    1,
+                    GAS_FOR_FT_TRANSFER
+                ).then(ext_self::on_ft_payout(
+                    id,
+                    token_id.clone(),
+                    amount,
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_CALLBACK
+                ));
+            }
+
+            ProposalType::Payout { amount, token_id: None } => {
+                self.assert_free_balance(amount.0);
+
+                Promise::new(target)
+                    .transfer(amount.0)
+                    .then(ext_self::on_executed(
+                        id,
+                        &env::current_account_id(),
+                        0,
+                        GAS_FOR_CALLBACK
+                    ));
+            }
+
+            ProposalType::AllowToken => {
+                self.token_contracts.insert(&target);
+
+                env::log(format!("Tokens of {} are accepted", target).as_bytes());
+            }
+
+            ProposalType::DisallowToken => {
+                self.token_contracts.remove(&target);
+
+                env::log(format!("Tokens of {} are no longer accepted", target).as_bytes());
+            }
+
+            ProposalType::ChangeConfig { purpose, bond, vote_period, grace_period } => {
+                if let Some(purpose) = purpose {
+                    self.purpose = purpose;
+                }
+                if let Some(bond) = bond {
+                    self.bond = bond.into();
+                }
+                if let Some(vote_period) = vote_period {
+                    self.vote_period = vote_period.into();
+                }
+                if let Some(grace_period) = grace_period {
+                    self.grace_period = grace_period.into();
+                }
+
+                env::log(b"DAO config updated");
+            }
+
+            ProposalType::UpgradeSelf { hash } => {
+                let code = self.blobs.get(&hash).expect("No blob with such hash");
+
+                Promise::new(env::current_account_id())
+                    .deploy_contract(code)
+                    .function_call(
+                        b"migrate".to_vec(),
+                        vec![],
+                        0,
+                        GAS_FOR_MIGRATE
+                    )
+                    .then(ext_self::on_executed(
+                        id,
+                        &env::current_account_id(),
+                        0,
+                        GAS_FOR_CALLBACK
+                    ));
+            }
+
+            ProposalType::TransferNft { nft_contract_id, token_id } => {
+                let token = NftToken { nft_contract_id, token_id };
+                self.nfts.remove(&token);
+
+                ext_nft::nft_transfer(
+                    target,
+                    token.token_id.clone(),
+                    None,
+                    Some(format!("Transfer of proposal {}", id)),
+                    &token.nft_contract_id,
+                    1,
+                    GAS_FOR_NFT_TRANSFER
+                ).then(ext_self::on_nft_transferred(
+                    id,
+                    token,
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_CALLBACK
+                ));
+            }
+
+            ProposalType::AddToRole { role } => {
+                match role {
+                    Role::Member => self.members.insert(&target),
+                    Role::Guardian => self.guardians.insert(&target),
+                    _ => env::panic(b"Only Member and Guardian roles are managed by proposals")
+                };
+
+                env::log(format!("{} added to {:?}", target, role).as_bytes());
+            }
+
+            ProposalType::RemoveFromRole { role } => {
+                match role {
+                    Role::Member => self.members.remove(&target),
+                    Role::Guardian => self.guardians.remove(&target),
+                    _ => env::panic(b"Only Member and Guardian roles are managed by proposals")
+                };
+
+                env::log(format!("{} removed from {:?}", target, role).as_bytes());
+            }
+
+            ProposalType::SetPermissions { kind, permissions } => {
+                self.permissions.insert(&kind, &permissions);
+
+                env::log(format!("Permissions for {:?} proposals updated", kind).as_bytes());
+            }
+
+            ProposalType::Election { .. } => {
+                let election = self.elections.get(&id).expect("No election for this proposal");
+                let winners = election.winners(&proposal.active_electorate(self));
+
+                //Outgoing members unbond their stake, winners keep theirs and add the candidate stake
+                let outgoing: Vec<Council> = self.council
+                    .values()
+                    .filter(|council| !winners.contains(&council.account))
+                    .collect();
+                //Like members who leave, the outgoing council no longer decides pending proposals
+                for council in outgoing.iter() {
+                    self.departed.insert(&council.account, &env::block_timestamp());
+                    self.start_unbonding(
+                        &council.account,
+                        council.locked_tokens,
+                        env::block_timestamp() + self.unbonding_period
+                    );
+                }
+
+                let zero: u128 = 0;
+                let elected: Vec<Council> = winners
+                    .iter()
+                    .map(|account| Council {
+                        account: account.clone(),
+                        weight: zero,
+                        locked_tokens: self.council.get(account).map_or(0, |council| council.locked_tokens)
+                            + election.candidates[account].stake,
+                        missed_votes: 0,
+                        suspended: false
+                    })
+                    .collect();
+                for (account, candidate) in election.candidates.iter() {
+                    if !winners.contains(account) && candidate.stake > 0 {
+                        self.start_unbonding(account, candidate.stake, env::block_timestamp());
+                    }
+                }
+
+                self.council.clear();
+                for council in elected.iter() {
+                    self.council.insert(&council.account, council);
+                }
+                let stale: Vec<AccountId> = self.delegations
+                    .iter()
+                    .filter(|(delegator, delegation)| {
+                        !winners.contains(delegator) || !winners.contains(&delegation.delegate)
+                    })
+                    .map(|(delegator, _)| delegator)
+                    .collect();
+                for delegator in stale.iter() {
+                    self.delegations.remove(delegator);
+                }
+                self.recompute_weights();
+                self.term_end = env::block_timestamp() + self.council_term;
+
+                env::log(format!("New council elected: {:?}, term ends at {}", winners, self.term_end).as_bytes());
+            }
+
+            ProposalType::FunctionCall { actions } => {
+                self.assert_free_balance(actions.iter().map(|action| action.deposit.0).sum());
+
+                let mut promise = Promise::new(target);
+                for action in actions {
+                    promise = promise.function_call(
+                        action.method_name.into_bytes(),
+                        action.args.into(),
+                        action.deposit.0,
+                        action.gas.0
+                    );
+                }
+
+                promise.then(ext_self::on_executed(
+                    id,
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_CALLBACK
+                ));
+            }
+        }
+    }
+
+    pub fn refund_bond(
+        &mut self,
+        id: u64
+        ) {
+        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
+
+        assert!(
+            proposal.status != ProposalStatus::Vote,
+            "Bond is held until voting ends"
+        );
+        assert!(
+            proposal.bond > 0,
+            "Bond already refunded"
+        );
+
+        self.send_bond_back(id, &mut proposal);
+    }
+
+    //Retries the stake refund of an application that did not pass
+    pub fn refund_stake(
+        &mut self,
+        id: u64
+        ) {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+
+        assert!(
+            proposal.status.is_finalized() && proposal.status != ProposalStatus::Executed,
+            "Stake is held until the application is closed"
+        );
+        assert!(
+            self.applications.get(&proposal.receiver) == Some(id),
+            "Stake already refunded"
+        );
+
+        self.send_stake_back(id, &proposal);
+    }
+
+    #[private]
+    pub fn on_stake_refunded(
+        &mut self,
+        id: u64,
+        account: AccountId,
+        amount: U128
+        ) {
+        if is_promise_success() {
+            env::log(format!("Stake of {} returned to {}", amount.0, account).as_bytes());
+        } else {
+            self.applications.insert(&account, &id);
+            self.locked_funds += amount.0;
+
+            env::log(format!("Stake refund of proposal {} failed, it can be retried with refund_stake", id).as_bytes());
+        }
+    }
+
+    #[private]
+    pub fn on_executed(
+        &mut self,
+        id: u64
+        ) {
+        if is_promise_success() {
+            env::log(format!("Proposal {} executed", id).as_bytes());
+        } else {
+            self.mark_execution_failed(id);
+        }
+    }
+
+    //Puts the tokens back into the treasury when ft_transfer fails
+    #[private]
+    pub fn on_ft_payout(
+        &mut self,
+        id: u64,
+        token_id: AccountId,
+        amount: U128
+        ) {
+        if is_promise_success() {
+            env::log(format!("Proposal {} executed", id).as_bytes());
+        } else {
+            let balance = self.ft_balances.get(&token_id).unwrap_or(0);
+            self.ft_balances.insert(&token_id, &(balance + amount.0));
+            self.mark_execution_failed(id);
+        }
+    }
+
+    //Takes the token back into custody when nft_transfer fails
+    #[private]
+    pub fn on_nft_transferred(
+        &mut self,
+        id: u64,
+        token: NftToken
+        ) {
+        if is_promise_success() {
+            env::log(format!("Proposal {} executed", id).as_bytes());
+        } else {
+            self.nfts.insert(&token);
+            self.mark_execution_failed(id);
+        }
+    }
+
+    #[private]
+    pub fn on_council_removed(
+        &mut self,
+        id: u64,
+        council: Council,
+        delegations: Vec<(AccountId, Delegation)>
+        ) {
+        if is_promise_success() {
+            env::log(format!("Proposal {} executed", id).as_bytes());
+            return;
+        }
+
+        //The stake never left, so the member keeps the seat as it was, suspension and missed votes included
+        self.council.insert(&council.account, &council);
+        self.locked_funds += council.locked_tokens;
+        for (delegator, delegation) in delegations.iter() {
+            //Delegations between members who left or re-delegated in the meantime stay dropped
+            if self.is_council(delegator)
+                && self.is_council(&delegation.delegate)
+                && self.delegations.get(delegator).is_none() {
+                self.delegations.insert(delegator, delegation);
+            }
+        }
+        self.recompute_weights();
+
+        self.mark_execution_failed(id);
+    }
+
+    #[private]
+    pub fn on_bond_refunded(
+        &mut self,
+        id: u64,
+        amount: U128
+        ) {
+        if is_promise_success() {
+            return;
+        }
+
+        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
+        proposal.bond += amount.0;
+        self.locked_funds += amount.0;
+        self.proposals.replace(id, &proposal);
+
+        env::log(format!("Bond refund for proposal {} failed, it can be retried with refund_bond", id).as_bytes());
+    }
+
+    //Also stops a failed execution from being retried
+    pub fn cancel_queued(
+        &mut self,
+        id: u64
+        ) {
+        assert!(
+            self.guardians.contains(&env::predecessor_account_id()),
+            "Only guardians can cancel a queued proposal"
+        );
+
+        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
+        assert!(
+            proposal.status == ProposalStatus::Queued || proposal.status == ProposalStatus::ExecutionFailed,
+            "Proposal is not queued for execution"
+        );
+
+        self.set_status(id, &mut proposal, ProposalStatus::Cancelled);
+        self.proposals.replace(id, &proposal);
+        self.send_stake_back(id, &proposal);
+
+        env::log(format!("Proposal {} cancelled by {}", id, env::predecessor_account_id()).as_bytes());
+    }
+
+    //Proposer withdraws a proposal that is still being voted on.
+    //The bond is refunded in full until the first vote, after that the cancel penalty is kept by the DAO
+    pub fn cancel_proposal(
+        &mut self,
+        id: u64
+        ) {
+        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
+        assert_eq!(
+            proposal.proposer,
+            env::predecessor_account_id(),
+            "Only the proposer can cancel the proposal"
+        );
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::Vote,
+            "Only proposals in voting can be cancelled"
+        );
+        assert!(
+            env::block_timestamp() <= proposal.vote_period_end,
+            "Voting period is over, call finalized instead"
+        );
+
+        let penalty = if proposal.votes.is_empty() {
+            0
+        } else {
+            proposal.bond * self.cancel_penalty / TOTAL_WEIGHT
+        };
+        proposal.bond -= penalty;
+        self.locked_funds -= penalty;
+
+        self.set_status(id, &mut proposal, ProposalStatus::Cancelled);
+        self.send_bond_back(id, &mut proposal);
+        self.send_stake_back(id, &proposal);
+        self.proposals.replace(id, &proposal);
+
+        env::log(format!("Proposal {} cancelled by proposer, penalty {}", id, penalty).as_bytes());
+    }
+
+    //Members and council put forward candidates while the election is open
+    pub fn nominate(
+        &mut self,
+        id: u64,
+        candidate: AccountId
+        ) {
+        let nominator = env::predecessor_account_id();
+        assert!(
+            self.has_role(&nominator, Role::Member) || self.has_role(&nominator, Role::Council),
+            "Only members and council can nominate"
+        );
+
+        let mut election = self.open_election(id);
+        assert!(
+            !election.candidates.contains_key(&candidate),
+            "Candidate is already nominated"
+        );
+        election.candidates.insert(candidate.clone(), Candidate { accepted: false, stake: 0 });
+        self.elections.insert(&id, &election);
+
+        env::log(format!("{} nominated {} in election {}", nominator, candidate, id).as_bytes());
+    }
+
+    //The attached deposit is escrowed as stake, candidates outside the council need at least min_stake
+    #[payable]
+    pub fn accept_nomination(
+        &mut self,
+        id: u64
+        ) {
+        let account = env::predecessor_account_id();
+        let mut election = self.open_election(id);
+        let is_council = self.is_council(&account);
+
+        let candidate = election.candidates
+            .get_mut(&account)
+            .expect("Not nominated in this election");
+        assert!(!candidate.accepted, "Nomination already accepted");
+        assert!(
+            is_council || env::attached_deposit() >= self.min_stake,
+            "Stake is below the minimum stake"
+        );
+
+        candidate.accepted = true;
+        candidate.stake = env::attached_deposit();
+        self.locked_funds += env::attached_deposit();
+        self.elections.insert(&id, &election);
+
+        env::log(format!("{} accepted the nomination in election {}", account, id).as_bytes());
+    }
+
+    //A new ballot replaces the previous one of the same voter
+    pub fn vote_election(
+        &mut self,
+        id: u64,
+        candidates: Vec<AccountId>
+        ) {
+        let voter = env::predecessor_account_id();
+        self.assert_permitted(ProposalKind::Election, Action::Vote, &voter);
+
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+        assert!(
+            proposal.electorate.contains_key(&voter) && !proposal.has_departed(self, &voter),
+            "Not in the electorate of this proposal"
+        );
+
+        let mut election = self.open_election(id);
+        assert!(
+            !candidates.is_empty() && candidates.len() as u64 <= election.seats,
+            "Vote for at least one and at most as many candidates as there are seats"
+        );
+        for (index, candidate) in candidates.iter().enumerate() {
+            assert!(
+                matches!(election.candidates.get(candidate), Some(candidate) if candidate.accepted),
+                "{} is not a candidate in this election",
+                candidate
+            );
+            assert!(!candidates[..index].contains(candidate), "Duplicate candidate in ballot");
+        }
+
+        election.ballots.insert(voter.clone(), candidates);
+        self.elections.insert(&id, &election);
+
+        env::log(format!("{} voted in election {}", voter, id).as_bytes());
+    }
+
+    pub fn get_election(
+        &self,
+        id: u64
+        ) -> ElectionView {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+        let election = self.elections.get(&id).expect("No election for this proposal");
+        let results = election.results(&proposal.active_electorate(self));
+
+        ElectionView {
+            seats: election.seats,
+            candidates: election.candidates
+                .iter()
+                .map(|(account, candidate)| CandidateView {
+                    account: account.clone(),
+                    accepted: candidate.accepted,
+                    stake: candidate.stake.into(),
+                    votes: results[account].into()
+                })
+                .collect(),
+            voter_count: election.ballots.len() as u64
+        }
+    }
+
+    //Anyone can call this. Members who missed max_missed_votes proposals in a row are suspended,
+    //at least one active member is always kept
+    pub fn prune_inactive(&mut self) -> Vec<AccountId> {
+        let members: Vec<Council> = self.council.values().collect();
+        let mut active = members.iter().filter(|item| !item.suspended).count();
+
+        let mut suspended: Vec<AccountId> = vec![];
+        for mut council in members.into_iter() {
+            if council.suspended || council.missed_votes < self.max_missed_votes || active <= 1 {
+                continue;
+            }
+
+            council.suspended = true;
+            self.council.insert(&council.account, &council);
+            active -= 1;
+
+            env::log(format!(
+                "Council member {} suspended after {} missed votes",
+                council.account, council.missed_votes
+            ).as_bytes());
+            suspended.push(council.account);
+        }
+
+        if !suspended.is_empty() {
+            self.recompute_weights();
+            for account in suspended.iter() {
+                self.departed.insert(account, &env::block_timestamp());
+            }
+        }
+        suspended
+    }
+
+    //Suspended members get their weight back, from the next proposal on
+    pub fn reactivate(&mut self) {
+        let account = env::predecessor_account_id();
+        let mut council = self.council.get(&account).expect("Only council members can reactivate");
+        assert!(council.suspended, "Council member is not suspended");
+
+        council.suspended = false;
+        council.missed_votes = 0;
+        self.council.insert(&account, &council);
+        self.recompute_weights();
+
+        env::log(format!("Council member {} reactivated", account).as_bytes());
+    }
+
+    pub fn get_config(&self) -> ConfigView {
+        ConfigView {
+            purpose: self.purpose.clone(),
+            bond: self.bond.into(),
+            vote_period: self.vote_period.into(),
+            grace_period: self.grace_period.into(),
+            unbonding_period: self.unbonding_period.into(),
+            min_stake: self.min_stake.into(),
+            council_term: self.council_term.into(),
+            term_end: self.term_end.into(),
+            max_missed_votes: self.max_missed_votes
+        }
+    }
+
+    pub fn get_cancel_penalty(&self) -> U128 {
+        self.cancel_penalty.into()
+    }
+
+    pub fn get_spam_threshold(&self) -> U128 {
+        self.spam_threshold.into()
+    }
+
+    pub fn get_locked_funds(&self) -> U128 {
+        self.locked_funds.into()
+    }
+
+    //Saves the raw input as a contract blob for an UpgradeSelf proposal.
+    //The deposit has to pay for the storage it takes
+    #[payable]
+    pub fn store_blob(&mut self) -> Base58CryptoHash {
+        let code = env::input().expect("Expected the wasm blob as input");
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&env::sha256(&code));
+        let hash = Base58CryptoHash::from(hash);
+        assert!(!self.blobs.contains_key(&hash), "Blob is already stored");
+
+        let initial_storage = env::storage_usage();
+        self.blobs.insert(&hash, &code);
+        let storage_cost = env::storage_byte_cost() * (env::storage_usage() - initial_storage) as u128;
+        assert!(env::attached_deposit() >= storage_cost, "Not enough deposit to store the blob");
+
+        let refund = env::attached_deposit() - storage_cost;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+
+        hash
+    }
+
+    pub fn has_blob(
+        &self,
+        hash: Base58CryptoHash
+        ) -> bool {
+        self.blobs.contains_key(&hash)
+    }
+
+    //Called by the UpgradeSelf proposal right after the new code is deployed.
+    //Every layout starts with the version, so it is read first to pick the layout of the rest
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate() -> Self {
+        let state = env::storage_read(STATE_KEY).expect("No state to migrate");
+        let version = <u32 as BorshDeserialize>::deserialize(&mut &state[..])
+            .expect("State does not start with a version");
+
+        let dao: DAO = match version {
+            STATE_VERSION => DAO::try_from_slice(&state).expect("Can not read the current state"),
+            _ => env::panic(format!("Can not migrate from state version {}", version).as_bytes())
+        };
+
+        env::log(format!("Migrated state from version {} to {}", version, STATE_VERSION).as_bytes());
+        dao
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    //Converts the state of the contract before per-proposal votes. The council is moved here,
+    //proposals follow in batches with migrate_legacy_proposals and the old votes with clear_legacy_votes.
+    //Settings the legacy contract did not have are passed in like in new
+    #[init(ignore_state)]
+    #[private]
+    #[allow(clippy::too_many_arguments)]
+    pub fn migrate_from_legacy(
+        _unbonding_period: WrappedDuration,
+        _min_stake: WrappedBalance,
+        _council_term: WrappedDuration,
+        _max_missed_votes: u64,
+        _cancel_penalty: U128,
+        _spam_threshold: U128,
+        _policy: HashMap<ProposalKind, VotePolicy>,
+        _guardians: Vec<AccountId>
+        ) -> Self {
+        assert!(
+            !env::storage_has_key(VOTES_MIGRATED_KEY) && !env::storage_has_key(LEGACY_CURSOR_KEY),
+            "State is not in the legacy layout"
+        );
+        let mut legacy: LegacyDAO = env::state_read().expect("No state to migrate");
+
+        let mut dao = Self::with_config(
+            legacy.purpose,
+            legacy.bond,
+            legacy.vote_period,
+            legacy.grace_period,
+            _unbonding_period,
+            _min_stake,
+            _council_term,
+            _max_missed_votes,
+            _cancel_penalty,
+            _spam_threshold,
+            _policy,
+            _guardians
+        );
+
+        //The new council map shares the b"c" prefix, so the legacy set is cleared first
+        let members = legacy.council.to_vec();
+        legacy.council.clear();
+        let zero: u128 = 0;
+        for member in members.iter() {
+            let council = Council {
+                account: member.account.clone(),
+                weight: zero,
+                locked_tokens: member.locked_tokens,
+                missed_votes: 0,
+                suspended: false
+            };
+            dao.council.insert(&council.account, &council);
+            dao.locked_funds += council.locked_tokens;
+        }
+        dao.recompute_weights();
+
+        //Same prefix and length, the elements are converted in place by migrate_legacy_proposals
+        dao.proposals = Vector::try_from_slice(&legacy.proposals.try_to_vec().unwrap()).unwrap();
+        env::storage_write(LEGACY_CURSOR_KEY, &0u64.to_le_bytes());
+        env::storage_write(LEGACY_END_KEY, &dao.proposals.len().to_le_bytes());
+
+        env::log(format!(
+            "Migrated council of {} members, {} legacy proposals left to convert",
+            members.len(), dao.proposals.len()
+        ).as_bytes());
+        dao
+    }
+
+    //Converts up to limit legacy proposals, starting where the previous batch ended.
+    //Open proposals stay open with the bond the legacy contract still holds, the others keep their result.
+    //Each copy of the shared votes map only reads the votes cast before the proposal was last saved,
+    //those are moved under the prefix of the proposal. Returns the next index
+    #[private]
+    pub fn migrate_legacy_proposals(
+        &mut self,
+        from_index: u64,
+        limit: u64
+        ) -> u64 {
+        let cursor = read_index(LEGACY_CURSOR_KEY).expect("No legacy proposals to migrate");
+        assert_eq!(cursor, from_index, "Continue from the last migrated index");
+        let legacy_end = read_index(LEGACY_END_KEY).expect("No legacy proposals to migrate");
+
+        let legacy: Vector<LegacyProposal> = Vector::try_from_slice(&self.proposals.try_to_vec().unwrap()).unwrap();
+        let mut shared_votes: Option<UnorderedMap<LegacyCouncil, LegacyVote>> = env::storage_read(LEGACY_VOTES_KEY)
+            .map(|bytes| UnorderedMap::try_from_slice(&bytes).unwrap());
+        let end = std::cmp::min(from_index.saturating_add(limit), legacy_end);
+
+        for id in from_index..end {
+            let old = legacy.get(id).unwrap();
+
+            let mut votes = UnorderedMap::new(votes_prefix(id));
+            for (council, vote) in old.votes.iter() {
+                let vote = match vote {
+                    LegacyVote::Yes => Vote::Yes,
+                    LegacyVote::No => Vote::No
+                };
+                votes.insert(&council.account, &vote);
+            }
+
+            //The longest copy covers every legacy vote, clear_legacy_votes removes them from it
+            let longer = match &shared_votes {
+                Some(shared_votes) => old.votes.len() > shared_votes.len(),
+                None => true
+            };
+            if longer {
+                shared_votes = Some(old.votes);
+            }
+
+            let (status, bond) = match old.status {
+                LegacyStatus::Vote => (ProposalStatus::Vote, self.bond),
+                LegacyStatus::Success => (ProposalStatus::Executed, 0),
+                LegacyStatus::Fail => (ProposalStatus::Fail, 0)
+            };
+            let kind = match old.kind {
+                LegacyProposalType::NewCouncil { amount } => ProposalType::NewCouncil { amount },
+                LegacyProposalType::DeleteCouncil => ProposalType::DeleteCouncil,
+                LegacyProposalType::Payout { amount } => ProposalType::Payout { amount, token_id: None }
+            };
+
+            //The legacy council voted on every proposal, it had no delegations
+            let electorate = self.electorate_for(kind.kind());
+            let mut proposal = Proposal {
+                status,
+                proposer: old.proposer,
+                receiver: old.receiver,
+                description: old.description,
+                kind,
+                bond,
+                vote_period_end: old.vote_period_end,
+                grace_period: self.grace_period,
+                grace_period_end: 0,
+                created_at: old.vote_period_end.saturating_sub(self.vote_period),
+                electorate,
+                delegations: HashMap::new(),
+                votes,
+                final_tally: None
+            };
+            if proposal.status.is_finalized() {
+                proposal.final_tally = Some(proposal.tally(self));
+            }
+
+            //replace would decode the legacy element as the new layout, so it is overwritten raw
+            self.proposals.replace_raw(id, &proposal.try_to_vec().unwrap());
+            self.index_proposal(id, &proposal);
+            self.locked_funds += proposal.bond;
+        }
+
+        if let Some(shared_votes) = shared_votes.filter(|shared_votes| !shared_votes.is_empty()) {
+            env::storage_write(LEGACY_VOTES_KEY, &shared_votes.try_to_vec().unwrap());
+        }
+        if end == legacy_end {
+            env::storage_remove(LEGACY_CURSOR_KEY);
+            env::storage_remove(LEGACY_END_KEY);
+            if !env::storage_has_key(LEGACY_VOTES_KEY) {
+                env::storage_write(VOTES_MIGRATED_KEY, &[1]);
+            }
+        } else {
+            env::storage_write(LEGACY_CURSOR_KEY, &end.to_le_bytes());
+        }
+
+        env::log(format!("Migrated legacy proposals {} to {}", from_index, end).as_bytes());
+        end
+    }
+
+    //Removes up to limit entries of the shared legacy votes map once every proposal is converted.
+    //Returns the number of entries left
+    #[private]
+    pub fn clear_legacy_votes(
+        &mut self,
+        limit: u64
+        ) -> u64 {
+        assert!(
+            !env::storage_has_key(LEGACY_CURSOR_KEY),
+            "Migrate every legacy proposal first"
+        );
+        let mut votes: UnorderedMap<LegacyCouncil, LegacyVote> = env::storage_read(LEGACY_VOTES_KEY)
+            .map(|bytes| UnorderedMap::try_from_slice(&bytes).unwrap())
+            .expect("No legacy votes to clear");
+
+        for _ in 0..std::cmp::min(limit, votes.len()) {
+            let key = votes.keys_as_vector().get(votes.len() - 1).unwrap();
+            votes.remove(&key);
+        }
+
+        if votes.is_empty() {
+            env::storage_remove(LEGACY_VOTES_KEY);
+            env::storage_write(VOTES_MIGRATED_KEY, &[1]);
+        } else {
+            env::storage_write(LEGACY_VOTES_KEY, &votes.try_to_vec().unwrap());
+        }
+        votes.len()
+    }
+
+    //State without council or proposals, every collection under its own prefix
+    #[allow(clippy::too_many_arguments)]
+    fn with_config(
+        _purpose: String,
+        _bond: Balance,
+        _vote_period: Duration,
+        _grace_period: Duration,
+        _unbonding_period: WrappedDuration,
+        _min_stake: WrappedBalance,
+        _council_term: WrappedDuration,
+        _max_missed_votes: u64,
+        _cancel_penalty: U128,
+        _spam_threshold: U128,
+        _policy: HashMap<ProposalKind, VotePolicy>,
+        _guardians: Vec<AccountId>
+        ) -> Self {
+        assert!(
+            ProposalKind::all().iter().all(|kind| _policy.contains_key(kind)),
+            "Policy must cover every proposal kind"
+        );
+        assert!(_council_term.0 > 0, "Council term must be positive");
+        assert!(_max_missed_votes > 0, "Max missed votes must be positive");
+        assert!(
+            _cancel_penalty.0 <= TOTAL_WEIGHT,
+            "Cancel penalty is a share of the bond in basis points"
+        );
+        assert!(
+            _spam_threshold.0 <= TOTAL_WEIGHT,
+            "Spam threshold is a share of the electorate in basis points"
+        );
+
+        let mut dao = Self {
+            version: STATE_VERSION,
+            purpose: _purpose,
+            bond: _bond,
+            vote_period: _vote_period,
+            grace_period: _grace_period,
+            unbonding_period: _unbonding_period.into(),
+            min_stake: _min_stake.into(),
+            council_term: _council_term.into(),
+            term_end: env::block_timestamp() + _council_term.0,
+            max_missed_votes: _max_missed_votes,
+            cancel_penalty: _cancel_penalty.into(),
+            spam_threshold: _spam_threshold.into(),
+            policy: UnorderedMap::new(b"o".to_vec()),
+            council: UnorderedMap::new(b"c".to_vec()),
+            delegations: UnorderedMap::new(b"d".to_vec()),
+            guardians: UnorderedSet::new(b"g".to_vec()),
+            proposals: Vector::new(b"p".to_vec()),
+            proposals_by_status: LookupMap::new(b"y".to_vec()),
+            proposals_by_proposer: LookupMap::new(b"f".to_vec()),
+            proposals_by_receiver: LookupMap::new(b"t".to_vec()),
+            proposals_by_kind: LookupMap::new(b"k".to_vec()),
+            blobs: LookupMap::new(b"b".to_vec()),
+            ft_balances: UnorderedMap::new(b"a".to_vec()),
+            token_contracts: UnorderedSet::new(b"j".to_vec()),
+            nfts: UnorderedSet::new(b"n".to_vec()),
+            applications: LookupMap::new(b"e".to_vec()),
+            unbonding: LookupMap::new(b"u".to_vec()),
+            members: UnorderedSet::new(b"m".to_vec()),
+            permissions: UnorderedMap::new(b"r".to_vec()),
+            elections: LookupMap::new(b"x".to_vec()),
+            departed: LookupMap::new(b"l".to_vec()),
+            open_proposals: LookupMap::new(b"h".to_vec()),
+            open_removals: LookupMap::new(b"i".to_vec()),
+            locked_funds: 0,
+        };
+
+        for (kind, policy) in _policy.iter() {
+            policy.assert_valid();
+            dao.policy.insert(kind, policy);
+        }
+
+        for guardian in _guardians.iter() {
+            dao.guardians.insert(guardian);
+        }
+
+        for kind in ProposalKind::all().iter() {
+            dao.permissions.insert(kind, &RolePermissions::default());
+        }
+
+        dao
+    }
+
+    //Adds to any stake already unbonding, the whole amount is released at the new date
+    fn start_unbonding(
+        &mut self,
+        account: &AccountId,
+        amount: Balance,
+        release_at: Duration
+        ) -> Unbonding {
+        let pending = self.unbonding.get(account);
+        let unbonding = Unbonding {
+            amount: (pending.as_ref().map_or(0, |unbonding| unbonding.amount.0) + amount).into(),
+            release_at: std::cmp::max(pending.map_or(0, |unbonding| unbonding.release_at.0), release_at).into()
+        };
+        self.unbonding.insert(account, &unbonding);
+        unbonding
+    }
+
+    fn create_proposal(
+        &mut self,
+        proposer: AccountId,
+        _proposal: ProposalInput
+        ) -> u64 {
+        _proposal.kind.assert_valid();
+        if let ProposalType::UpgradeSelf { hash } = &_proposal.kind {
+            assert!(self.blobs.contains_key(hash), "No blob with such hash");
+        }
+        assert!(
+            env::block_timestamp() <= self.term_end || _proposal.kind.kind() == ProposalKind::Election,
+            "Council term is over, an election is due"
+        );
+
+        let policy = self.policy_for(&_proposal.kind);
+        let vote_period = policy.vote_period.map_or(self.vote_period, |period| period.0);
+
+        let electorate = self.electorate_for(_proposal.kind.kind());
+        let delegations = self.delegations_for(_proposal.kind.kind(), &electorate);
+
+        let id = self.proposals.len();
+        let p = Proposal {
+            status: ProposalStatus::Vote,
+            proposer,
+            receiver: _proposal.target,
+            description: _proposal.description,
+            kind: _proposal.kind,
+            bond: self.bond,
+            vote_period_end: env::block_timestamp() + vote_period,
+            grace_period: self.grace_period,
+            grace_period_end: 0,
+            created_at: env::block_timestamp(),
+            electorate,
+            delegations,
+            votes: UnorderedMap::new(votes_prefix(id)),
+            final_tally: None
+        };
+
+        if let ProposalType::Election { seats } = p.kind {
+            let election = Election {
+                seats,
+                candidates: HashMap::new(),
+                ballots: HashMap::new()
+            };
+            self.elections.insert(&id, &election);
+        }
+
+        self.proposals.push(&p);
+        self.index_proposal(id, &p);
+        self.locked_funds += p.bond;
+        id
+    }
+
+    //Returns the escrowed stake of an application that did not pass
+    fn send_stake_back(
+        &mut self,
+        id: u64,
+        proposal: &Proposal
+        ) {
+        if let ProposalType::NewCouncil { amount } = proposal.kind {
+            if self.applications.get(&proposal.receiver) != Some(id) {
+                return;
+            }
+            self.applications.remove(&proposal.receiver);
+            self.locked_funds -= amount.0;
+
+            Promise::new(proposal.receiver.clone())
+                .transfer(amount.0)
+                .then(ext_self::on_stake_refunded(
+                    id,
+                    proposal.receiver.clone(),
+                    amount,
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_CALLBACK
+                ));
+        }
+
+        //Candidate stakes can be withdrawn right away when the election does not go through
+        if let ProposalType::Election { .. } = proposal.kind {
+            let mut election = self.elections.get(&id).expect("No election for this proposal");
+            for (account, candidate) in election.candidates.iter_mut() {
+                if candidate.stake > 0 {
+                    self.start_unbonding(account, candidate.stake, env::block_timestamp());
+                    candidate.stake = 0;
+                }
+            }
+            self.elections.insert(&id, &election);
+        }
+    }
+
+    fn send_bond_back(
+        &mut self,
+        id: u64,
+        proposal: &mut Proposal
+        ) {
+        let amount = proposal.bond;
+        if amount == 0 {
+            return;
+        }
+
+        proposal.bond = 0;
+        self.proposals.replace(id, proposal);
+        self.locked_funds -= amount;
+
+        Promise::new(proposal.proposer.clone())
+            .transfer(amount)
+            .then(ext_self::on_bond_refunded(
+                id,
+                amount.into(),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_CALLBACK
+            ));
+    }
+
+    //Treasury payouts and call deposits can not touch the funds held for others
+    //or the balance that pays for the contract storage
+    fn assert_free_balance(
+        &self,
+        amount: Balance
+        ) {
+        let storage_cost = env::storage_byte_cost() * env::storage_usage() as u128;
+        let free = env::account_balance().saturating_sub(self.locked_funds + storage_cost);
+        assert!(free >= amount, "Not enough free balance in the treasury");
+    }
+
+    fn mark_execution_failed(
+        &mut self,
+        id: u64
+        ) {
+        let mut proposal = self.proposals.get(id).expect("No proposal with such id");
+        self.set_status(id, &mut proposal, ProposalStatus::ExecutionFailed);
+        self.proposals.replace(id, &proposal);
+
+        env::log(format!("Execution of proposal {} failed, it can be retried with execute", id).as_bytes());
+    }
+
+    //Votes stay open while the proposal is queued, so members can still overturn it
+    //until the grace period ends. Returns None when the voting period ran out instead
+    fn proposal_for_voting(
+        &mut self,
+        id: u64
+        ) -> Option<Proposal> {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+
+        match proposal.status {
+            ProposalStatus::Vote => {
+                if proposal.vote_period_end < env::block_timestamp() {
+                    env::log(b"Voting period expried, finalizing the proposal");
+                    self.finalized(id);
+                    return None;
+                }
+            }
+
+            ProposalStatus::Queued => {
+                assert!(
+                    env::block_timestamp() < proposal.grace_period_end,
+                    "Grace period is over, proposal can only be executed"
+                );
+            }
+
+            _ => env::panic(b"Proposal already finalized")
+        }
+
+        Some(proposal)
+    }
+
+    //Drops the delegation of the account and every delegation pointing to it,
+    //returns them so they can be restored
+    fn remove_delegations(
+        &mut self,
+        account: &AccountId
+        ) -> Vec<(AccountId, Delegation)> {
+        let removed: Vec<(AccountId, Delegation)> = self.delegations
+            .iter()
+            .filter(|(delegator, delegation)| delegator == account || delegation.delegate == *account)
+            .collect();
+        for (delegator, _) in removed.iter() {
+            self.delegations.remove(delegator);
+        }
+        removed
+    }
+
+    //Resets the count of council members who took part, directly or through a delegate.
+    //A miss is only counted when the proposal ran until the end of its voting period,
+    //a proposal decided early gave the others no chance to vote
+    fn record_participation(
+        &mut self,
+        id: u64,
+        proposal: &Proposal
+        ) {
+        let voters: Vec<AccountId> = match proposal.kind {
+            ProposalType::Election { .. } => self.elections
+                .get(&id)
+                .map_or(vec![], |election| election.ballots.keys().cloned().collect()),
+            _ => proposal.votes.keys().collect()
+        };
+
+        let ran_full_period = env::block_timestamp() > proposal.vote_period_end;
+        for account in proposal.active_electorate(self).keys() {
+            let mut council = match self.council.get(account) {
+                Some(council) => council,
+                None => continue
+            };
+
+            let voted = voters.contains(account)
+                || proposal.delegations
+                    .get(account)
+                    .filter(|delegate| voters.contains(delegate))
+                    .is_some();
+            if voted {
+                council.missed_votes = 0;
+            } else if ran_full_period {
+                council.missed_votes += 1;
+            } else {
+                continue;
+            }
+            self.council.insert(account, &council);
+        }
+    }
+
+    fn open_election(
+        &self,
+        id: u64
+        ) -> Election {
+        let proposal = self.proposals.get(id).expect("No proposal with such id");
+        assert!(
+            proposal.status == ProposalStatus::Vote && env::block_timestamp() <= proposal.vote_period_end,
+            "Election is not open"
+        );
+        self.elections.get(&id).expect("No election for this proposal")
+    }
+
+    //Elections run for the whole voting period. Quorum is the weight of the ballots cast,
+    //and there have to be enough accepted candidates to fill every seat
+    fn election_status(
+        &self,
+        id: u64,
+        proposal: &Proposal
+        ) -> VoteOutcome {
+        if env::block_timestamp() <= proposal.vote_period_end {
+            return VoteOutcome::Pending;
+        }
+
+        let election = self.elections.get(&id).expect("No election for this proposal");
+        let electorate = proposal.active_electorate(self);
+        let tally = VoteTally {
+            total: electorate.values().sum(),
+            ..VoteTally::default()
+        };
+        let participation: u128 = election.ballots
+            .keys()
+            .map(|voter| electorate.get(voter).copied().unwrap_or(0))
+            .sum();
+        let accepted = election.candidates.values().filter(|candidate| candidate.accepted).count() as u64;
+
+        if tally.share_of(participation) < self.policy_for(&proposal.kind).quorum.0 {
+            VoteOutcome::Expired
+        } else if accepted < election.seats {
+            VoteOutcome::Failed
+        } else {
+            VoteOutcome::Passed
+        }
+    }
+
+    fn update_vote_status(
+        &mut self,
+        id: u64,
+        proposal: &mut Proposal
+        ) {
+        self.proposals.replace(id, proposal);
+
+        //Elections are only decided by finalized once voting ends
+        if let ProposalType::Election { .. } = proposal.kind {
+            return;
+        }
+
+        match proposal.status {
+            ProposalStatus::Queued => {
+                if proposal.vote_blocker(self).is_some() {
+                    self.set_status(id, proposal, ProposalStatus::Fail);
+                    self.proposals.replace(id, proposal);
+                    self.send_stake_back(id, proposal);
+
+                    env::log(format!("Queued proposal {} cancelled by counter-vote", id).as_bytes());
+                }
+            }
+
+            _ => {
+                let outcome = proposal.vote_status(self);
+                if outcome != VoteOutcome::Pending {
+                    self.finalized(id);
+                }
+            }
+        }
+    }
+
+    //Delegations that cover the kind and stay within the electorate
+    fn delegations_for(
+        &self,
+        kind: ProposalKind,
+        electorate: &HashMap<AccountId, u128>
+        ) -> HashMap<AccountId, AccountId> {
+        self.delegations
+            .iter()
+            .filter(|(delegator, delegation)| {
+                delegation.covers(&kind)
+                    && electorate.contains_key(delegator)
+                    && electorate.contains_key(&delegation.delegate)
+            })
+            .map(|(delegator, delegation)| (delegator, delegation.delegate))
+            .collect()
+    }
+
+    fn index_proposal(
+        &mut self,
+        id: u64,
+        proposal: &Proposal
+        ) {
+        let mut by_status = self.proposals_by_status
+            .get(&proposal.status)
+            .unwrap_or_else(|| TreeMap::new(index_prefix(b"Y", &proposal.status)));
+        by_status.insert(&id, &());
+        self.proposals_by_status.insert(&proposal.status, &by_status);
+
+        let mut by_proposer = self.proposals_by_proposer
+            .get(&proposal.proposer)
+            .unwrap_or_else(|| Vector::new(index_prefix(b"F", &proposal.proposer)));
+        by_proposer.push(&id);
+        self.proposals_by_proposer.insert(&proposal.proposer, &by_proposer);
+
+        let mut by_receiver = self.proposals_by_receiver
+            .get(&proposal.receiver)
+            .unwrap_or_else(|| Vector::new(index_prefix(b"T", &proposal.receiver)));
+        by_receiver.push(&id);
+        self.proposals_by_receiver.insert(&proposal.receiver, &by_receiver);
+
+        let kind = proposal.kind.kind();
+        let mut by_kind = self.proposals_by_kind
+            .get(&kind)
+            .unwrap_or_else(|| Vector::new(index_prefix(b"K", &kind)));
+        by_kind.push(&id);
+        self.proposals_by_kind.insert(&kind, &by_kind);
+
+        if is_open(&proposal.status) {
+            self.count_open(proposal, true);
+        }
+    }
+
+    //Keeps open_proposals and open_removals in step with proposals entering or leaving Vote and Queued
+    fn count_open(
+        &mut self,
+        proposal: &Proposal,
+        opened: bool
+        ) {
+        let adjust = |count: u64| if opened { count + 1 } else { count - 1 };
+
+        let count = self.open_proposals.get(&proposal.proposer).unwrap_or(0);
+        self.open_proposals.insert(&proposal.proposer, &adjust(count));
+
+        if proposal.kind.kind() == ProposalKind::DeleteCouncil {
+            let count = self.open_removals.get(&proposal.receiver).unwrap_or(0);
+            self.open_removals.insert(&proposal.receiver, &adjust(count));
+        }
+    }
+
+    //Every status change goes through here so proposals_by_status stays in sync
+    fn set_status(
+        &mut self,
+        id: u64,
+        proposal: &mut Proposal,
+        status: ProposalStatus
+        ) {
+        assert!(
+            proposal.status.can_transition_to(&status),
+            "Proposal can not move from {:?} to {:?}",
+            proposal.status,
+            status
+        );
+
+        let mut previous = self.proposals_by_status
+            .get(&proposal.status)
+            .expect("Proposal is missing from the status index");
+        previous.remove(&id);
+        self.proposals_by_status.insert(&proposal.status, &previous);
+
+        let mut next = self.proposals_by_status
+            .get(&status)
+            .unwrap_or_else(|| TreeMap::new(index_prefix(b"Y", &status)));
+        next.insert(&id, &());
+        self.proposals_by_status.insert(&status, &next);
+
+        //Later delegation or council changes no longer move the result of a closed proposal
+        if status.is_finalized() && proposal.final_tally.is_none() {
+            proposal.final_tally = Some(proposal.tally(self));
+        }
+        if is_open(&proposal.status) && !is_open(&status) {
+            self.count_open(proposal, false);
+        }
+
+        proposal.status = status;
+    }
+
+    fn proposal_views(
+        &self,
+        ids: &Vector<u64>,
+        from_index: u64,
+        limit: u64
+        ) -> Vec<ProposalView> {
+        (from_index..std::cmp::min(from_index.saturating_add(limit), ids.len()))
+            .map(|index| {
+                let id = ids.get(index).unwrap();
+                self.proposals.get(id).unwrap().to_view(id, self)
+            })
+            .collect()
+    }
+
+    fn execution_blocker(
+        &self,
+        proposal: &Proposal
+        ) -> Option<&'static str> {
+        match &proposal.kind {
+            ProposalType::DeleteCouncil if !self.is_council(&proposal.receiver) => {
+                Some("receiver is not a council member")
+            }
+            ProposalType::DeleteCouncil if self.council.len() <= 1 => {
+                Some("the last council member can not be removed")
+            }
+            ProposalType::TransferNft { nft_contract_id, token_id } => {
+                let token = NftToken {
+                    nft_contract_id: nft_contract_id.clone(),
+                    token_id: token_id.clone()
+                };
+                if self.nfts.contains(&token) {
+                    None
+                } else {
+                    Some("the DAO does not hold this token")
+                }
+            }
+            _ => None
+        }
+    }
+
+    fn has_role(
+        &self,
+        account: &AccountId,
+        role: Role
+        ) -> bool {
+        match role {
+            Role::Council => self.is_council(account),
+            Role::Member => self.members.contains(account),
+            Role::Guardian => self.guardians.contains(account),
+            Role::Public => true
+        }
+    }
+
+    fn assert_permitted(
+        &self,
+        kind: ProposalKind,
+        action: Action,
+        account: &AccountId
+        ) {
+        let permissions = self.permissions.get(&kind).unwrap_or_default();
+        assert!(
+            permissions.roles_for(action).iter().any(|role| self.has_role(account, *role)),
+            "{} has no role allowed to {:?} {:?} proposals",
+            account,
+            action,
+            kind
+        );
+    }
+
+    //Council members vote with their stake weight. Accounts that only hold another voting role
+    //get the weight of an average council seat
+    fn electorate_for(
+        &self,
+        kind: ProposalKind
+        ) -> HashMap<AccountId, u128> {
+        let permissions = self.permissions.get(&kind).unwrap_or_default();
+        let seat_weight = TOTAL_WEIGHT / std::cmp::max(self.council.len() as u128, 1);
+
+        let mut electorate = HashMap::new();
+        for role in permissions.vote.iter() {
+            match role {
+                Role::Council => {
+                    for item in self.council.values().filter(|item| !item.suspended) {
+                        electorate.insert(item.account, item.weight);
+                    }
+                }
+                Role::Member => {
+                    for account in self.members.iter() {
+                        electorate.entry(account).or_insert(seat_weight);
+                    }
+                }
+                Role::Guardian => {
+                    for account in self.guardians.iter() {
+                        electorate.entry(account).or_insert(seat_weight);
+                    }
+                }
+                Role::Public => {}
+            }
+        }
+        electorate
+    }
+
+    fn is_council(
+        &self,
+        account: &AccountId
+        ) -> bool {
+        self.council
+            .get(account)
+            .is_some()
+    }
+
+    fn policy_for(
+        &self,
+        kind: &ProposalType
+        ) -> VotePolicy {
+        self.policy
+            .get(&kind.kind())
+            .expect("No voting policy for this proposal kind")
+    }
+
+    //Largest remainder method: every member gets the floor of their stake share,
+    //the basis points left over go to the largest remainders, ties broken by account id
+    fn recompute_weights(
+        &mut self
+        ) {
+        let mut members: Vec<Council> = self.council
+            .values()
+            .collect();
+
+        if members.is_empty() {
+            return;
+        }
+
+        members.sort_by(|a, b| a.account.cmp(&b.account));
+
+        let total_stake: Balance = members
+            .iter()
+            .filter(|item| !item.suspended)
+            .map(|item| item.locked_tokens)
+            .sum();
+
+        //Without any stake every active member weighs the same
+        let shares: Vec<Balance> = members
+            .iter()
+            .map(|item| if item.suspended { 0 } else if total_stake == 0 { 1 } else { item.locked_tokens })
+            .collect();
+        let total_shares: Balance = shares.iter().sum();
+        if total_shares == 0 {
+            return;
+        }
+
+        let mut remainders: Vec<(u128, usize)> = Vec::with_capacity(members.len());
+        for (index, item) in members.iter_mut().enumerate() {
+            let exact = shares[index] * TOTAL_WEIGHT;
+            item.weight = exact / total_shares;
+            remainders.push((exact % total_shares, index));
+        }
+
+        let assigned: u128 = members
+            .iter()
+            .map(|item| item.weight)
+            .sum();
+
+        remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        for (_, index) in remainders.iter().take((TOTAL_WEIGHT - assigned) as usize) {
+            members[*index].weight += 1;
+        }
+
+        for item in members.iter() {
+            self.council.insert(&item.account, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    const VOTE_PERIOD: Duration = 100;
+    const GRACE_PERIOD: Duration = 50;
+    const UNBONDING_PERIOD: Duration = 200;
+    const COUNCIL_TERM: u64 = 1_000;
+    const MAX_MISSED_VOTES: u64 = 2;
+    const CANCEL_PENALTY: u128 = 2_000;
+    const SPAM_THRESHOLD: u128 = 5_000;
+
+    fn account(id: usize) -> AccountId {
+        accounts(id).into()
+    }
+
+    //The DAO runs as fargo, storage is kept between calls to testing_env
+    fn set_context(
+        predecessor: usize,
+        deposit: Balance,
+        timestamp: u64
+        ) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(5))
+            .predecessor_account_id(accounts(predecessor))
+            .attached_deposit(deposit)
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    //Callbacks run as the DAO itself and see the transfer they follow as failed
+    fn set_failed_callback_context() {
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(5))
+                .predecessor_account_id(accounts(5))
+                .build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+    }
+
+    fn vote_policy(
+        threshold: u128,
+        quorum: u128
+        ) -> VotePolicy {
+        VotePolicy {
+            threshold: threshold.into(),
+            quorum: quorum.into(),
+            vote_period: None
+        }
+    }
+
+    fn policy() -> HashMap<ProposalKind, VotePolicy> {
+        ProposalKind::all()
+            .into_iter()
+            .map(|kind| (kind, vote_policy(5_000, 5_000)))
+            .collect()
+    }
+
+    //Alice owns the DAO, dan is the guardian, proposals need no bond
+    fn dao_with_policy(
+        owner_stake: Balance,
+        policy: HashMap<ProposalKind, VotePolicy>
+        ) -> DAO {
+        set_context(0, owner_stake, 0);
+        DAO::new(
+            "test".to_string(),
+            0.into(),
+            VOTE_PERIOD.into(),
+            GRACE_PERIOD.into(),
+            UNBONDING_PERIOD.into(),
+            0.into(),
+            COUNCIL_TERM.into(),
+            MAX_MISSED_VOTES,
+            CANCEL_PENALTY.into(),
+            SPAM_THRESHOLD.into(),
+            policy,
+            vec![account(3)]
+        )
+    }
+
+    fn new_dao(owner_stake: Balance) -> DAO {
+        dao_with_policy(owner_stake, policy())
+    }
+
+    fn propose(dao: &mut DAO) -> u64 {
+        propose_kind(dao, ProposalType::Payout { amount: 1.into(), token_id: None }, 1)
+    }
+
+    fn propose_kind(
+        dao: &mut DAO,
+        kind: ProposalType,
+        target: usize
+        ) -> u64 {
+        dao.add_proposal(ProposalInput {
+            target: account(target),
+            description: "test".to_string(),
+            kind
+        })
+    }
+
+    fn add_council(
+        dao: &mut DAO,
+        index: usize,
+        locked_tokens: Balance
+        ) {
+        let council = Council {
+            account: account(index),
+            weight: 0,
+            locked_tokens,
+            missed_votes: 0,
+            suspended: false
+        };
+        dao.council.insert(&council.account, &council);
+        dao.locked_funds += locked_tokens;
+        dao.recompute_weights();
+    }
+
+    fn weight_of(
+        dao: &DAO,
+        index: usize
+        ) -> u128 {
+        dao.council.get(&account(index)).unwrap().weight
+    }
+
+    fn total_weight(dao: &DAO) -> u128 {
+        dao.council.values().map(|item| item.weight).sum()
+    }
+
+    fn votes_of(
+        dao: &DAO,
+        id: u64
+        ) -> Vec<(AccountId, Vote)> {
+        dao.proposals.get(id).unwrap().votes
+            .iter()
+            .collect()
+    }
+
+    //Voters are alice, bob and so on, each with the given weight,
+    //members who did not vote can be added to the electorate afterwards
+    fn proposal_with_votes(votes: &[(u128, Vote)]) -> Proposal {
+        let mut proposal = Proposal {
+            status: ProposalStatus::Vote,
+            proposer: account(0),
+            receiver: account(0),
+            description: "test".to_string(),
+            kind: ProposalType::Payout { amount: 1.into(), token_id: None },
+            bond: 0,
+            vote_period_end: VOTE_PERIOD,
+            grace_period: GRACE_PERIOD,
+            grace_period_end: 0,
+            created_at: 0,
+            electorate: HashMap::new(),
+            delegations: HashMap::new(),
+            votes: UnorderedMap::new(b"v".to_vec()),
+            final_tally: None
+        };
+        for (index, (weight, vote)) in votes.iter().enumerate() {
+            proposal.electorate.insert(account(index), *weight);
+            proposal.votes.insert(&account(index), vote);
+        }
+        proposal
+    }
+
+    #[test]
+    fn vote_passes_with_quorum_and_approval() {
+        let dao = new_dao(0);
+        let proposal = proposal_with_votes(&[(60, Vote::Yes), (10, Vote::No)]);
+
+        assert_eq!(proposal.vote_blocker(&dao), None);
+        assert_eq!(proposal.vote_status(&dao), VoteOutcome::Passed);
+    }
+
+    #[test]
+    fn abstentions_count_towards_quorum_only() {
+        let mut dao = new_dao(0);
+        let mut proposal = proposal_with_votes(&[(30, Vote::Yes), (30, Vote::Abstain)]);
+        proposal.electorate.insert(account(2), 40);
+        assert_eq!(proposal.vote_blocker(&dao), Some(VoteBlocker::Approval));
+
+        dao.policy.insert(&ProposalKind::Payout, &vote_policy(5_000, 7_000));
+        assert_eq!(proposal.vote_blocker(&dao), Some(VoteBlocker::Quorum));
+    }
+
+    #[test]
+    fn vote_without_quorum_expires_once_voting_ends() {
+        let dao = new_dao(0);
+        let mut proposal = proposal_with_votes(&[(40, Vote::Yes)]);
+        proposal.electorate.insert(account(1), 60);
+        assert_eq!(proposal.vote_blocker(&dao), Some(VoteBlocker::Quorum));
+        assert_eq!(proposal.vote_status(&dao), VoteOutcome::Pending);
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        assert_eq!(proposal.vote_status(&dao), VoteOutcome::Expired);
+    }
+
+    #[test]
+    fn payout_threshold_applies_to_payouts_only() {
+        let mut policy = policy();
+        policy.insert(ProposalKind::Payout, vote_policy(7_000, 5_000));
+        let dao = dao_with_policy(0, policy);
+
+        let mut proposal = proposal_with_votes(&[(60, Vote::Yes)]);
+        proposal.electorate.insert(account(1), 40);
+        assert_eq!(proposal.vote_blocker(&dao), Some(VoteBlocker::Approval));
+        assert_eq!(dao.policy_for(&ProposalType::DeleteCouncil).threshold.0, 5_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Policy must cover every proposal kind")]
+    fn policy_must_cover_every_kind() {
+        set_context(0, 0, 0);
+        let mut policy = HashMap::new();
+        policy.insert(ProposalKind::Payout, vote_policy(5_000, 5_000));
+        DAO::new("test".to_string(), 0.into(), VOTE_PERIOD.into(), 0.into(), 0.into(), 0.into(), COUNCIL_TERM.into(), MAX_MISSED_VOTES, 0.into(), 0.into(), policy, vec![]);
+    }
+
+    #[test]
+    fn vote_can_be_changed_then_retracted() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose(&mut dao);
+
+        dao.vote(id, Vote::Yes);
+        dao.vote(id, Vote::No);
+        assert_eq!(votes_of(&dao, id), vec![(account(0), Vote::No)]);
+
+        dao.retract_vote(id);
+        assert!(votes_of(&dao, id).is_empty());
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Vote);
+    }
+
+    #[test]
+    #[should_panic(expected = "Already voted")]
+    fn same_vote_can_not_be_cast_twice() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose(&mut dao);
+
+        dao.vote(id, Vote::Yes);
+        dao.vote(id, Vote::Yes);
+    }
+
+    #[test]
+    #[should_panic(expected = "No vote to retract")]
+    fn retract_needs_a_vote() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose(&mut dao);
+
+        dao.retract_vote(id);
+    }
+
+    #[test]
+    fn direct_vote_overrides_delegation() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 3);
+        add_council(&mut dao, 2, 2);
+        set_context(1, 0, 0);
+        dao.delegate(account(2), None);
+        let id = propose(&mut dao);
+
+        set_context(2, 0, 0);
+        dao.vote(id, Vote::Yes);
+        let tally = dao.proposals.get(id).unwrap().tally(&dao);
+        assert_eq!((tally.yes, tally.no), (3_333, 0));
+
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::No);
+        let tally = dao.proposals.get(id).unwrap().tally(&dao);
+        assert_eq!((tally.yes, tally.no), (1_333, 2_000));
+    }
+
+    #[test]
+    fn electorate_is_fixed_when_the_proposal_is_created() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 3);
+        let id = propose(&mut dao);
+
+        //Later changes to the council do not reach the open proposal
+        add_council(&mut dao, 2, 2);
+        set_context(1, 0, 0);
+        dao.delegate(account(2), None);
+        dao.vote(id, Vote::Yes);
+
+        let proposal = dao.proposals.get(id).unwrap();
+        assert!(!proposal.electorate.contains_key(&account(2)));
+        assert!(proposal.delegations.is_empty());
+        assert_eq!(proposal.tally(&dao).yes, 2_308);
+        assert_eq!(weight_of(&dao, 1), 2_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not in the electorate of this proposal")]
+    fn members_added_later_can_not_vote() {
+        let mut dao = new_dao(10);
+        let id = propose(&mut dao);
+
+        add_council(&mut dao, 2, 2);
+        set_context(2, 0, 0);
+        dao.vote(id, Vote::Yes);
+    }
+
+    #[test]
+    fn weights_follow_stake_and_add_up_to_total() {
+        let mut dao = new_dao(100);
+        add_council(&mut dao, 1, 200);
+        add_council(&mut dao, 2, 300);
+
+        assert_eq!(weight_of(&dao, 0), 1_667);
+        assert_eq!(weight_of(&dao, 1), 3_333);
+        assert_eq!(weight_of(&dao, 2), 5_000);
+        assert_eq!(total_weight(&dao), TOTAL_WEIGHT);
+    }
+
+    #[test]
+    fn weight_ties_go_to_lower_account_id() {
+        let mut dao = new_dao(100);
+        //Insertion order does not matter
+        add_council(&mut dao, 2, 100);
+        add_council(&mut dao, 1, 100);
+
+        assert_eq!(weight_of(&dao, 0), 3_334);
+        assert_eq!(weight_of(&dao, 1), 3_333);
+        assert_eq!(weight_of(&dao, 2), 3_333);
+
+        dao.recompute_weights();
+        assert_eq!(weight_of(&dao, 0), 3_334);
+        assert_eq!(total_weight(&dao), TOTAL_WEIGHT);
+    }
+
+    #[test]
+    fn zero_stake_splits_weight_equally() {
+        let mut dao = new_dao(0);
+        add_council(&mut dao, 1, 0);
+        add_council(&mut dao, 2, 0);
+
+        assert_eq!(weight_of(&dao, 0), 3_334);
+        assert_eq!(weight_of(&dao, 1), 3_333);
+        assert_eq!(weight_of(&dao, 2), 3_333);
+        assert_eq!(total_weight(&dao), TOTAL_WEIGHT);
+    }
+
+    fn legacy_proposal(status: LegacyStatus) -> LegacyProposal {
+        LegacyProposal {
+            status,
+            proposer: account(2),
+            receiver: account(2),
+            description: "legacy".to_string(),
+            kind: LegacyProposalType::Payout { amount: 5.into() },
+            vote_period_end: VOTE_PERIOD,
+            votes: UnorderedMap::new(b"v".to_vec())
+        }
+    }
+
+    #[test]
+    fn legacy_proposals_keep_their_votes() {
+        set_context(0, 0, 0);
+        let alice = LegacyCouncil { account: account(0), weight: 0, locked_tokens: 100 };
+        let bob = LegacyCouncil { account: account(1), weight: 0, locked_tokens: 100 };
+        let mut council = UnorderedSet::new(b"c".to_vec());
+        council.insert(&alice);
+        council.insert(&bob);
+
+        let mut proposals = Vector::new(b"p".to_vec());
+        proposals.push(&legacy_proposal(LegacyStatus::Fail));
+        let mut open = legacy_proposal(LegacyStatus::Vote);
+        open.votes.insert(&alice, &LegacyVote::Yes);
+        proposals.push(&open);
+        env::state_write(&LegacyDAO {
+            purpose: "legacy".to_string(),
+            bond: 0,
+            vote_period: VOTE_PERIOD,
+            grace_period: 0,
+            council,
+            proposals
+        });
+
+        let mut dao = DAO::migrate_from_legacy(
+            UNBONDING_PERIOD.into(),
+            0.into(),
+            COUNCIL_TERM.into(),
+            MAX_MISSED_VOTES,
+            CANCEL_PENALTY.into(),
+            SPAM_THRESHOLD.into(),
+            policy(),
+            vec![]
+        );
+        assert_eq!(dao.migrate_legacy_proposals(0, 1), 1);
+        //Proposals added in the middle of the migration are already in the new layout
+        let id = propose(&mut dao);
+        assert_eq!(dao.migrate_legacy_proposals(1, 10), 2);
+        assert_eq!(dao.clear_legacy_votes(10), 0);
+        assert!(env::storage_has_key(VOTES_MIGRATED_KEY));
+
+        assert_eq!(dao.proposals.get(0).unwrap().status, ProposalStatus::Fail);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Vote);
+        let open = dao.proposals.get(1).unwrap();
+        assert_eq!(open.status, ProposalStatus::Vote);
+        assert_eq!(dao.open_proposals.get(&account(2)), Some(1));
+        assert_eq!(open.tally(&dao).yes, 5_000);
+
+        set_context(1, 0, 0);
+        dao.vote(1, Vote::Yes);
+        assert_eq!(dao.proposals.get(1).unwrap().status, ProposalStatus::Queued);
+    }
+
+    #[test]
+    fn proposal_view_shows_the_tally() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose(&mut dao);
+        dao.vote(id, Vote::Yes);
+
+        let view = dao.get_proposal(id);
+        assert_eq!(view.tally.yes.0, 3_333);
+        assert_eq!(view.voter_count, 1);
+        assert_eq!(view.status, ProposalStatus::Vote);
+    }
+
+    #[test]
+    fn tally_is_frozen_once_finalized() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose(&mut dao);
+        dao.vote(id, Vote::Yes);
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        dao.finalized(id);
+
+        let mut proposal = dao.proposals.get(id).unwrap();
+        proposal.votes.insert(&account(1), &Vote::Yes);
+        dao.proposals.replace(id, &proposal);
+
+        let view = dao.get_proposal(id);
+        assert_eq!(view.status, ProposalStatus::Expired);
+        assert_eq!(view.tally.yes.0, 3_333);
+    }
+
+    fn ids(views: Vec<ProposalView>) -> Vec<u64> {
+        views.iter().map(|view| view.id).collect()
+    }
+
+    #[test]
+    fn proposal_listings_are_paged() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        for _ in 0..3 {
+            propose(&mut dao);
+        }
+
+        assert_eq!(ids(dao.get_proposals(0, 2)), vec![0, 1]);
+        assert_eq!(ids(dao.get_proposals(2, 10)), vec![2]);
+        assert_eq!(ids(dao.get_proposals_by_proposer(account(0), 1, 10)), vec![1, 2]);
+        assert_eq!(ids(dao.get_proposals_by_receiver(account(1), 0, 1)), vec![0]);
+        assert_eq!(ids(dao.get_proposals_by_kind(ProposalKind::Payout, 2, 10)), vec![2]);
+        assert!(dao.get_proposals_by_kind(ProposalKind::DeleteCouncil, 0, 10).is_empty());
+    }
+
+    #[test]
+    fn status_listing_follows_status_changes() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        for _ in 0..3 {
+            propose(&mut dao);
+        }
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        dao.finalized(1);
+
+        assert_eq!(ids(dao.get_proposals_by_status(ProposalStatus::Vote, 0, 10)), vec![0, 2]);
+        assert_eq!(ids(dao.get_proposals_by_status(ProposalStatus::Vote, 1, 10)), vec![2]);
+        assert_eq!(ids(dao.get_proposals_by_status(ProposalStatus::Expired, 0, 10)), vec![1]);
+    }
+
+    //bob outweighs alice, his Yes vote alone passes a proposal
+    fn queued_proposal(dao: &mut DAO) -> u64 {
+        let id = propose(dao);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Queued);
+        id
+    }
+
+    #[test]
+    fn queued_proposal_runs_after_the_grace_period() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+
+        set_context(2, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Executed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Grace period has not ended yet")]
+    fn queued_proposal_waits_for_the_grace_period() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+
+        set_context(2, 0, GRACE_PERIOD - 1);
+        dao.execute(id);
+    }
+
+    #[test]
+    fn counter_vote_cancels_a_queued_proposal() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+
+        set_context(1, 0, 1);
+        dao.vote(id, Vote::No);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Fail);
+    }
+
+    #[test]
+    fn guardian_cancels_a_queued_proposal() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+
+        set_context(3, 0, 1);
+        dao.cancel_queued(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Cancelled);
+    }
+
+    #[test]
+    fn removing_a_member_twice_fails_the_second_proposal() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        add_council(&mut dao, 2, 5);
+        let first = propose_kind(&mut dao, ProposalType::DeleteCouncil, 2);
+        let second = propose_kind(&mut dao, ProposalType::DeleteCouncil, 2);
+        set_context(1, 0, 0);
+        dao.vote(first, Vote::Yes);
+        dao.vote(second, Vote::Yes);
+
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(first);
+        dao.execute(second);
+        assert!(!dao.is_council(&account(2)));
+        assert_eq!(dao.proposals.get(second).unwrap().status, ProposalStatus::Fail);
+    }
+
+    #[test]
+    fn failed_payout_can_be_retried() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+
+        set_context(2, 0, GRACE_PERIOD);
+        dao.execute(id);
+        set_failed_callback_context();
+        dao.on_executed(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::ExecutionFailed);
+
+        set_context(2, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Executed);
+    }
+
+    #[test]
+    fn failed_stake_return_restores_the_member() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        add_council(&mut dao, 2, 5);
+        set_context(0, 0, 0);
+        dao.delegate(account(2), None);
+        let id = propose_kind(&mut dao, ProposalType::DeleteCouncil, 2);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+
+        let council = dao.council.get(&account(2)).unwrap();
+        let delegations = vec![(account(0), dao.delegations.get(&account(0)).unwrap())];
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert!(!dao.is_council(&account(2)));
+        assert!(dao.delegations.get(&account(0)).is_none());
+
+        set_failed_callback_context();
+        dao.on_council_removed(id, council, delegations);
+        assert_eq!(dao.council.get(&account(2)).unwrap().locked_tokens, 5);
+        assert_eq!(dao.delegations.get(&account(0)).unwrap().delegate, account(2));
+        assert_eq!(total_weight(&dao), TOTAL_WEIGHT);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::ExecutionFailed);
+    }
+
+    #[test]
+    fn failed_bond_refund_keeps_the_bond() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        dao.bond = 7;
+        set_context(0, 7, 0);
+        let id = queued_proposal(&mut dao);
+        assert_eq!(dao.proposals.get(id).unwrap().bond, 0);
+
+        set_failed_callback_context();
+        dao.on_bond_refunded(id, 7.into());
+        assert_eq!(dao.proposals.get(id).unwrap().bond, 7);
+    }
+
+    #[test]
+    fn last_member_removal_fails_instead_of_running() {
+        let mut dao = new_dao(10);
+        let id = propose_kind(&mut dao, ProposalType::DeleteCouncil, 0);
+        dao.vote(id, Vote::Yes);
+
+        set_context(0, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert!(dao.is_council(&account(0)));
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Fail);
+    }
+
+    #[test]
+    fn finalized_proposals_allow_no_further_transitions() {
+        for status in [ProposalStatus::Fail, ProposalStatus::Expired, ProposalStatus::Cancelled].iter() {
+            assert!(status.is_finalized());
+            assert!(!status.can_transition_to(&ProposalStatus::Queued));
+            assert!(!status.can_transition_to(&ProposalStatus::Executed));
+        }
+        assert!(!ProposalStatus::Vote.can_transition_to(&ProposalStatus::Executed));
+        assert!(ProposalStatus::ExecutionFailed.can_transition_to(&ProposalStatus::Executed));
+    }
+
+    #[test]
+    fn guardian_cancels_a_failed_execution() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+        set_context(2, 0, GRACE_PERIOD);
+        dao.execute(id);
+        set_failed_callback_context();
+        dao.on_executed(id);
+
+        set_context(3, 0, GRACE_PERIOD);
+        dao.cancel_queued(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Cancelled);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal is not queued for execution")]
+    fn cancelled_proposal_can_not_be_executed() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = queued_proposal(&mut dao);
+        set_context(3, 0, 1);
+        dao.cancel_queued(id);
+
+        set_context(2, 0, GRACE_PERIOD);
+        dao.execute(id);
+    }
+
+    #[test]
+    fn cancel_before_any_vote_refunds_the_whole_bond() {
+        let mut dao = new_dao(10);
+        dao.bond = 100;
+        set_context(0, 100, 0);
+        let id = propose(&mut dao);
+
+        dao.cancel_proposal(id);
+        let proposal = dao.proposals.get(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Cancelled);
+        assert_eq!(proposal.bond, 0);
+        assert_eq!(get_logs(), vec!["Proposal 0 cancelled by proposer, penalty 0"]);
+    }
+
+    #[test]
+    fn cancel_after_a_vote_keeps_the_penalty() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 5);
+        dao.bond = 100;
+        set_context(0, 100, 0);
+        let id = propose(&mut dao);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::No);
+
+        set_context(0, 0, 1);
+        dao.cancel_proposal(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Cancelled);
+        assert_eq!(get_logs(), vec!["Proposal 0 cancelled by proposer, penalty 20"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the proposer can cancel the proposal")]
+    fn only_the_proposer_can_cancel() {
+        let mut dao = new_dao(10);
+        let id = propose(&mut dao);
+
+        set_context(1, 0, 0);
+        dao.cancel_proposal(id);
+    }
+
+    #[test]
+    fn no_share_above_spam_threshold_is_rejected() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        dao.bond = 100;
+        set_context(0, 100, 0);
+        let id = propose(&mut dao);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::No);
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        assert_eq!(dao.proposals.get(id).unwrap().vote_status(&dao), VoteOutcome::Rejected);
+        dao.finalized(id);
+        let proposal = dao.proposals.get(id).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Rejected);
+        //The bond is forfeited, nothing is left to refund
+        assert_eq!(proposal.bond, 0);
+        assert_eq!(get_logs(), vec!["Proposal 0 rejected as spam, bond of 100 forfeited"]);
+    }
+
+    #[test]
+    fn no_share_below_spam_threshold_only_fails() {
+        let mut dao = new_dao(40);
+        add_council(&mut dao, 1, 30);
+        add_council(&mut dao, 2, 30);
+        let id = propose(&mut dao);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::No);
+        set_context(2, 0, 0);
+        dao.vote(id, Vote::Yes);
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        dao.finalized(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Fail);
+    }
+
+    fn change_config(
+        grace_period: Option<Duration>,
+        purpose: Option<&str>
+        ) -> ProposalType {
+        ProposalType::ChangeConfig {
+            purpose: purpose.map(|purpose| purpose.to_string()),
+            bond: None,
+            vote_period: None,
+            grace_period: grace_period.map(|period| period.into())
+        }
+    }
+
+    #[test]
+    fn executed_change_config_updates_the_dao() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose_kind(&mut dao, change_config(Some(500), Some("new purpose")), 0);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+        let config = dao.get_config();
+        assert_eq!(config.purpose, "new purpose");
+        assert_eq!(config.grace_period.0, 500);
+        assert_eq!(config.vote_period.0, VOTE_PERIOD);
+    }
+
+    #[test]
+    fn open_proposals_keep_the_grace_period_they_were_created_with() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let open = propose(&mut dao);
+        let id = propose_kind(&mut dao, change_config(Some(500), None), 0);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+
+        dao.vote(open, Vote::Yes);
+        assert_eq!(dao.proposals.get(open).unwrap().grace_period_end, 2 * GRACE_PERIOD);
+    }
+
+    #[test]
+    #[should_panic(expected = "ChangeConfig must update at least one field")]
+    fn change_config_must_change_something() {
+        let mut dao = new_dao(10);
+        propose_kind(&mut dao, change_config(None, None), 0);
+    }
+
+    fn function_call(gas: u64) -> ProposalType {
+        ProposalType::FunctionCall {
+            actions: vec![ActionCall {
+                method_name: "ping".to_string(),
+                args: b"{}".to_vec().into(),
+                deposit: 0.into(),
+                gas: gas.into()
+            }]
+        }
+    }
+
+    #[test]
+    fn function_call_proposal_runs_its_actions() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = propose_kind(&mut dao, function_call(10_000_000_000_000), 2);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Executed);
+        set_failed_callback_context();
+        dao.on_executed(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::ExecutionFailed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Each action needs some gas")]
+    fn function_call_actions_need_gas() {
+        let mut dao = new_dao(10);
+        propose_kind(&mut dao, function_call(0), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "FunctionCall must have at least one action")]
+    fn function_call_needs_an_action() {
+        let mut dao = new_dao(10);
+        propose_kind(&mut dao, ProposalType::FunctionCall { actions: vec![] }, 2);
+    }
+
+    fn store_blob(
+        dao: &mut DAO,
+        code: &[u8]
+        ) -> Base58CryptoHash {
+        let mut context = VMContextBuilder::new()
+            .current_account_id(accounts(5))
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(10u128.pow(24))
+            .build();
+        context.input = code.to_vec();
+        testing_env!(context);
+        dao.store_blob()
+    }
+
+    #[test]
+    fn upgrade_runs_with_a_stored_blob() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let hash = store_blob(&mut dao, b"wasm");
+        assert!(dao.has_blob(hash));
+
+        set_context(0, 0, 0);
+        let id = propose_kind(&mut dao, ProposalType::UpgradeSelf { hash }, 5);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Executed);
+    }
+
+    #[test]
+    #[should_panic(expected = "No blob with such hash")]
+    fn upgrade_needs_a_stored_blob() {
+        let mut dao = new_dao(10);
+        let hash = Base58CryptoHash::from([0u8; 32]);
+        propose_kind(&mut dao, ProposalType::UpgradeSelf { hash }, 5);
+    }
+
+    #[test]
+    fn migrate_keeps_the_current_state() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        env::state_write(&dao);
+
+        let migrated = DAO::migrate();
+        assert_eq!(migrated.get_version(), STATE_VERSION);
+        assert_eq!(weight_of(&migrated, 1), 6_667);
+    }
+
+    //Proposed by alice, passed by bob's vote and executed once the grace period is over
+    fn run_proposal(
+        dao: &mut DAO,
+        kind: ProposalType,
+        target: usize
+        ) -> u64 {
+        set_context(0, 0, 0);
+        let id = propose_kind(dao, kind, target);
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+        id
+    }
+
+    fn receive_tokens(
+        dao: &mut DAO,
+        token: usize,
+        amount: Balance
+        ) -> Balance {
+        set_context(token, 0, 0);
+        match dao.ft_on_transfer(account(0), amount.into(), "".to_string()) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("Expected a value")
+        }
+    }
+
+    #[test]
+    fn tokens_are_only_accepted_from_allowed_contracts() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        assert_eq!(receive_tokens(&mut dao, 4, 50), 50);
+        assert!(dao.ft_balances.is_empty());
+
+        run_proposal(&mut dao, ProposalType::AllowToken, 4);
+        assert_eq!(receive_tokens(&mut dao, 4, 50), 0);
+        assert_eq!(dao.get_ft_balance(account(4)).0, 50);
+
+        run_proposal(&mut dao, ProposalType::DisallowToken, 4);
+        assert_eq!(receive_tokens(&mut dao, 4, 50), 50);
+        assert_eq!(dao.get_ft_balance(account(4)).0, 50);
+    }
+
+    #[test]
+    fn failed_token_payout_returns_the_tokens_to_the_treasury() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        run_proposal(&mut dao, ProposalType::AllowToken, 4);
+        receive_tokens(&mut dao, 4, 50);
+
+        let payout = ProposalType::Payout { amount: 30.into(), token_id: Some(account(4)) };
+        let id = run_proposal(&mut dao, payout, 2);
+        assert_eq!(dao.get_ft_balance(account(4)).0, 20);
+
+        set_failed_callback_context();
+        dao.on_ft_payout(id, account(4), 30.into());
+        assert_eq!(dao.get_ft_balance(account(4)).0, 50);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::ExecutionFailed);
+    }
+
+    fn receive_nft(
+        dao: &mut DAO,
+        nft_contract: usize,
+        token_id: &str
+        ) -> bool {
+        set_context(nft_contract, 0, 0);
+        match dao.nft_on_transfer(account(0), account(0), token_id.to_string(), "".to_string()) {
+            PromiseOrValue::Value(returned) => returned,
+            PromiseOrValue::Promise(_) => panic!("Expected a value")
+        }
+    }
+
+    fn transfer_nft(token_id: &str) -> ProposalType {
+        ProposalType::TransferNft {
+            nft_contract_id: account(4),
+            token_id: token_id.to_string()
+        }
+    }
+
+    #[test]
+    fn nfts_are_only_kept_from_allowed_contracts() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        assert!(receive_nft(&mut dao, 4, "1"));
+        assert!(dao.nfts.is_empty());
+
+        run_proposal(&mut dao, ProposalType::AllowToken, 4);
+        assert!(!receive_nft(&mut dao, 4, "1"));
+        assert_eq!(dao.get_nfts(0, 10).len(), 1);
+    }
+
+    #[test]
+    fn failed_nft_transfer_keeps_the_token() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        run_proposal(&mut dao, ProposalType::AllowToken, 4);
+        receive_nft(&mut dao, 4, "1");
+
+        let id = run_proposal(&mut dao, transfer_nft("1"), 2);
+        assert!(dao.nfts.is_empty());
+        set_failed_callback_context();
+        dao.on_nft_transferred(id, NftToken { nft_contract_id: account(4), token_id: "1".to_string() });
+        assert_eq!(dao.get_nfts(0, 10).len(), 1);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::ExecutionFailed);
+    }
+
+    #[test]
+    fn transfer_of_a_token_no_longer_held_fails() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        run_proposal(&mut dao, ProposalType::AllowToken, 4);
+        receive_nft(&mut dao, 4, "1");
+        set_context(0, 0, 0);
+        let first = propose_kind(&mut dao, transfer_nft("1"), 2);
+        let second = propose_kind(&mut dao, transfer_nft("1"), 3);
+        set_context(1, 0, 0);
+        dao.vote(first, Vote::Yes);
+        dao.vote(second, Vote::Yes);
+
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(first);
+        dao.execute(second);
+        assert_eq!(dao.proposals.get(first).unwrap().status, ProposalStatus::Executed);
+        assert_eq!(dao.proposals.get(second).unwrap().status, ProposalStatus::Fail);
+    }
+
+    fn apply(
+        dao: &mut DAO,
+        applicant: usize,
+        stake: Balance
+        ) -> u64 {
+        set_context(applicant, stake, 0);
+        dao.apply_for_council("candidate".to_string())
+    }
+
+    #[test]
+    fn elected_applicant_locks_the_escrowed_stake() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = apply(&mut dao, 2, 50);
+        assert_eq!(dao.get_application(account(2)), Some(id));
+        assert_eq!(dao.get_locked_funds().0, 80);
+
+        set_context(1, 0, 0);
+        dao.vote(id, Vote::Yes);
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert_eq!(dao.council.get(&account(2)).unwrap().locked_tokens, 50);
+        assert_eq!(dao.get_application(account(2)), None);
+        assert_eq!(dao.get_locked_funds().0, 80);
+    }
+
+    #[test]
+    fn expired_application_returns_the_stake() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = apply(&mut dao, 2, 50);
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        dao.finalized(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Expired);
+        assert_eq!(dao.get_application(account(2)), None);
+        assert_eq!(dao.get_locked_funds().0, 30);
+
+        set_failed_callback_context();
+        dao.on_stake_refunded(id, account(2), 50.into());
+        assert_eq!(dao.get_application(account(2)), Some(id));
+        assert_eq!(dao.get_locked_funds().0, 80);
+    }
+
+    #[test]
+    #[should_panic(expected = "Candidates apply themselves with apply_for_council")]
+    fn new_council_proposals_go_through_applications() {
+        let mut dao = new_dao(10);
+        propose_kind(&mut dao, ProposalType::NewCouncil { amount: 50.into() }, 2);
+    }
+
+    #[test]
+    fn leaving_member_no_longer_counts_on_pending_proposals() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        add_council(&mut dao, 2, 10);
+        let id = propose(&mut dao);
+        assert_eq!(dao.proposals.get(id).unwrap().tally(&dao).total, TOTAL_WEIGHT);
+
+        set_context(1, 0, 10);
+        dao.leave_council();
+        assert!(!dao.is_council(&account(1)));
+        assert_eq!(dao.proposals.get(id).unwrap().tally(&dao).total, 5_000);
+        let unbonding = dao.get_unbonding(account(1)).unwrap();
+        assert_eq!((unbonding.amount.0, unbonding.release_at.0), (20, 10 + UNBONDING_PERIOD));
+        assert_eq!(dao.get_locked_funds().0, 40);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cancel or finalize your pending proposals before leaving")]
+    fn member_with_open_proposals_can_not_leave() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        propose(&mut dao);
+        dao.leave_council();
+    }
+
+    #[test]
+    fn queued_proposal_that_lost_its_majority_fails() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        add_council(&mut dao, 2, 10);
+        let id = queued_proposal(&mut dao);
+        set_context(2, 0, 0);
+        dao.vote(id, Vote::No);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Queued);
+
+        set_context(1, 0, 1);
+        dao.leave_council();
+        set_context(2, 0, GRACE_PERIOD);
+        dao.execute(id);
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Fail);
+        assert_eq!(get_logs().last().unwrap(), &format!("Queued proposal {} lost its majority and failed", id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unbonding period has not ended yet")]
+    fn withdraw_waits_for_the_unbonding_period() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        set_context(1, 0, 0);
+        dao.leave_council();
+
+        set_context(1, 0, UNBONDING_PERIOD - 1);
+        dao.withdraw();
+    }
+
+    #[test]
+    fn failed_withdrawal_can_be_retried() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        set_context(1, 0, 0);
+        dao.leave_council();
+
+        set_context(1, 0, UNBONDING_PERIOD);
+        dao.withdraw();
+        assert!(dao.get_unbonding(account(1)).is_none());
+        assert_eq!(dao.get_locked_funds().0, 10);
+
+        let unbonding = Unbonding {
+            amount: 20.into(),
+            release_at: UNBONDING_PERIOD.into()
+        };
+        set_failed_callback_context();
+        dao.on_withdrawn(account(1), unbonding);
+        assert_eq!(dao.get_unbonding(account(1)).unwrap().amount.0, 20);
+        assert_eq!(dao.get_locked_funds().0, 30);
+    }
+
+    #[test]
+    fn increased_stake_adds_weight() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 10);
+        assert_eq!(weight_of(&dao, 1), 5_000);
+
+        set_context(1, 20, 0);
+        dao.increase_stake();
+        assert_eq!(dao.council.get(&account(1)).unwrap().locked_tokens, 30);
+        assert_eq!(weight_of(&dao, 1), 7_500);
+        assert_eq!(dao.get_locked_funds().0, 40);
+    }
+
+    #[test]
+    fn unstaked_amounts_add_up_in_one_unbonding() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 30);
+        set_context(1, 0, 0);
+        dao.request_unstake(10.into());
+        set_context(1, 0, 5);
+        dao.request_unstake(5.into());
+
+        assert_eq!(dao.council.get(&account(1)).unwrap().locked_tokens, 15);
+        let unbonding = dao.get_unbonding(account(1)).unwrap();
+        assert_eq!((unbonding.amount.0, unbonding.release_at.0), (15, 5 + UNBONDING_PERIOD));
+        assert_eq!(dao.get_locked_funds().0, 40);
+    }
+
+    #[test]
+    #[should_panic(expected = "Remaining stake would fall below the minimum stake")]
+    fn unstake_keeps_the_minimum_stake() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 30);
+        dao.min_stake = 25;
+        set_context(1, 0, 0);
+        dao.request_unstake(10.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Stake is below the minimum stake")]
+    fn applications_need_the_minimum_stake() {
+        let mut dao = new_dao(10);
+        dao.min_stake = 25;
+        apply(&mut dao, 2, 20);
+    }
+
+    fn permissions(
+        create: Vec<Role>,
+        vote: Vec<Role>,
+        execute: Vec<Role>
+        ) -> RolePermissions {
+        RolePermissions {
+            create,
+            vote,
+            execute
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "has no role allowed to Create Payout proposals")]
+    fn only_permitted_roles_create_proposals() {
+        let mut dao = new_dao(10);
+        dao.permissions.insert(
+            &ProposalKind::Payout,
+            &permissions(vec![Role::Council], vec![Role::Council], vec![Role::Public])
+        );
+        set_context(4, 0, 0);
+        propose(&mut dao);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no role allowed to Execute Payout proposals")]
+    fn only_permitted_roles_execute_proposals() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        dao.permissions.insert(
+            &ProposalKind::Payout,
+            &permissions(vec![Role::Public], vec![Role::Council], vec![Role::Guardian])
+        );
+        let id = queued_proposal(&mut dao);
+
+        set_context(1, 0, GRACE_PERIOD);
+        dao.execute(id);
+    }
+
+    #[test]
+    fn members_vote_with_an_average_seat() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        run_proposal(&mut dao, ProposalType::AddToRole { role: Role::Member }, 4);
+        assert!(dao.members.contains(&account(4)));
+
+        let vote_by_members = permissions(vec![Role::Public], vec![Role::Council, Role::Member], vec![Role::Public]);
+        run_proposal(
+            &mut dao,
+            ProposalType::SetPermissions { kind: ProposalKind::Payout, permissions: vote_by_members },
+            0
+        );
+        set_context(0, 0, 0);
+        let id = propose(&mut dao);
+        let electorate = dao.proposals.get(id).unwrap().electorate;
+        assert_eq!(electorate.get(&account(4)), Some(&5_000));
+        assert_eq!(electorate.get(&account(1)), Some(&weight_of(&dao, 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Public can not vote")]
+    fn public_can_not_be_given_the_vote() {
+        let mut dao = new_dao(10);
+        let open_vote = permissions(vec![Role::Public], vec![Role::Public], vec![Role::Public]);
+        propose_kind(&mut dao, ProposalType::SetPermissions { kind: ProposalKind::Payout, permissions: open_vote }, 0);
+    }
+
+    #[test]
+    fn election_winners_are_ranked_by_weight() {
+        let candidate = |accepted| Candidate { accepted, stake: 0 };
+        let mut election = Election {
+            seats: 2,
+            candidates: HashMap::new(),
+            ballots: HashMap::new()
+        };
+        election.candidates.insert(account(2), candidate(true));
+        election.candidates.insert(account(3), candidate(true));
+        election.candidates.insert(account(4), candidate(true));
+        election.candidates.insert(account(5), candidate(false));
+        election.ballots.insert(account(0), vec![account(3), account(4)]);
+        election.ballots.insert(account(1), vec![account(2), account(4)]);
+
+        let electorate: HashMap<AccountId, u128> = vec![(account(0), 40), (account(1), 40)].into_iter().collect();
+        //dan and charlie tie, the lower account id wins the seat
+        assert_eq!(election.winners(&electorate), vec![account(4), account(2)]);
+    }
+
+    //alice nominates carol, who stakes 30, and both members vote for her
+    fn run_election(dao: &mut DAO) -> u64 {
+        set_context(0, 0, 0);
+        let id = propose_kind(dao, ProposalType::Election { seats: 1 }, 0);
+        dao.nominate(id, account(2));
+        set_context(2, 30, 0);
+        dao.accept_nomination(id);
+        for voter in 0..2 {
+            set_context(voter, 0, 0);
+            dao.vote_election(id, vec![account(2)]);
+        }
+
+        set_context(0, 0, VOTE_PERIOD + 1);
+        dao.finalized(id);
+        set_context(0, 0, VOTE_PERIOD + 1 + GRACE_PERIOD);
+        dao.execute(id);
+        id
+    }
+
+    #[test]
+    fn election_replaces_the_council() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let id = run_election(&mut dao);
+
+        assert_eq!(dao.proposals.get(id).unwrap().status, ProposalStatus::Executed);
+        assert_eq!(dao.council.keys().collect::<Vec<AccountId>>(), vec![account(2)]);
+        assert_eq!(dao.council.get(&account(2)).unwrap().locked_tokens, 30);
+        assert_eq!(dao.get_unbonding(account(1)).unwrap().amount.0, 20);
+        assert_eq!(dao.get_locked_funds().0, 60);
+        assert_eq!(dao.term_end, VOTE_PERIOD + 1 + GRACE_PERIOD + COUNCIL_TERM);
+    }
+
+    #[test]
+    fn election_cuts_the_old_council_out_of_pending_proposals() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        let pending = propose(&mut dao);
+        run_election(&mut dao);
+
+        let proposal = dao.proposals.get(pending).unwrap();
+        assert!(proposal.has_departed(&dao, &account(0)));
+        assert!(proposal.has_departed(&dao, &account(1)));
+        assert_eq!(proposal.tally(&dao).total, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Council term is over, an election is due")]
+    fn proposals_after_the_term_need_an_election() {
+        let mut dao = new_dao(10);
+        set_context(0, 0, COUNCIL_TERM + 1);
+        propose(&mut dao);
+    }
+
+    fn missed_votes(
+        dao: &DAO,
+        index: usize
+        ) -> u64 {
+        dao.council.get(&account(index)).unwrap().missed_votes
+    }
+
+    fn set_missed_votes(
+        dao: &mut DAO,
+        index: usize,
+        missed_votes: u64
+        ) {
+        let mut council = dao.council.get(&account(index)).unwrap();
+        council.missed_votes = missed_votes;
+        dao.council.insert(&council.account, &council);
+    }
+
+    #[test]
+    fn missed_votes_only_count_on_proposals_that_ran_their_full_period() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        //Passed by bob before alice had a chance to vote
+        queued_proposal(&mut dao);
+        assert_eq!((missed_votes(&dao, 0), missed_votes(&dao, 1)), (0, 0));
+
+        set_context(0, 0, 0);
+        let id = propose(&mut dao);
+        set_context(0, 0, VOTE_PERIOD + 1);
+        dao.finalized(id);
+        assert_eq!((missed_votes(&dao, 0), missed_votes(&dao, 1)), (1, 1));
+    }
+
+    #[test]
+    fn pruning_keeps_one_active_member() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        set_missed_votes(&mut dao, 0, MAX_MISSED_VOTES);
+        set_missed_votes(&mut dao, 1, MAX_MISSED_VOTES);
+
+        assert_eq!(dao.prune_inactive(), vec![account(0)]);
+        assert!(dao.council.get(&account(0)).unwrap().suspended);
+        assert_eq!(weight_of(&dao, 0), 0);
+        assert_eq!(weight_of(&dao, 1), TOTAL_WEIGHT);
+        assert!(dao.prune_inactive().is_empty());
+    }
+
+    #[test]
+    fn suspended_member_is_left_out_until_reactivated() {
+        let mut dao = new_dao(10);
+        add_council(&mut dao, 1, 20);
+        add_council(&mut dao, 2, 10);
+        let pending = propose(&mut dao);
+        set_missed_votes(&mut dao, 1, MAX_MISSED_VOTES);
+
+        set_context(0, 0, 10);
+        assert_eq!(dao.prune_inactive(), vec![account(1)]);
+        assert_eq!(dao.proposals.get(pending).unwrap().tally(&dao).total, 5_000);
+
+        set_context(1, 0, 20);
+        dao.reactivate();
+        assert_eq!(weight_of(&dao, 1), 5_000);
+        assert_eq!(missed_votes(&dao, 1), 0);
+        //Proposals created before the suspension stay without him
+        assert_eq!(dao.proposals.get(pending).unwrap().tally(&dao).total, 5_000);
+        set_context(0, 0, 20);
+        let id = propose(&mut dao);
+        assert_eq!(dao.proposals.get(id).unwrap().tally(&dao).total, TOTAL_WEIGHT);
     }
 }